@@ -1,15 +1,38 @@
-use std::fmt::{Debug, Display};
+
+use rand::RngCore;
 use crate::lib::search_problem::SearchProblem;
-use crate::lib::search_problem::HiddenState;
-use crate::lib::search_problem::Observation;
-use rand::seq::SliceRandom;
 
-mod search_problem;
-mod tzf8;
-mod utils;
-mod search;
+pub(crate) mod search_problem;
+pub(crate) mod tzf8;
+pub(crate) mod utils;
+mod rollout;
+pub(crate) mod search;
+#[cfg(test)]
+mod test_games;
 
 
+// Estimates the discounted return of all the players for the rewards received after a state,
+// drawing any random numbers from the given generator
 pub(crate) trait Simulator<P: SearchProblem> {
-    fn simulate(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32) -> Vec<(P::Player, f32)>;
+    fn simulate(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32, rng: &mut dyn RngCore) -> Vec<(P::Player, f32)>;
+
+    // Also returns the actions played in the simulation, for all-moves-as-first statistics.
+    // Simulators that don't keep track of them return no actions.
+    fn simulate_with_actions(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32, rng: &mut dyn RngCore) -> Rollout<P> {
+        Rollout {
+            values: self.simulate(problem, state, horizon, discount, rng),
+            actions: vec![],
+        }
+    }
+}
+
+// Picks the action the player to move plays in a rollout, among the given legal ones
+pub(crate) trait RolloutPolicy<P: SearchProblem> {
+    fn choose(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action], rng: &mut dyn RngCore) -> P::Action;
+}
+
+// The returns of a simulation and the actions every player played in it, in order
+pub(crate) struct Rollout<P: SearchProblem> {
+    pub(crate) values: Vec<(P::Player, f32)>,
+    pub(crate) actions: Vec<(P::Player, P::Action)>,
 }
@@ -0,0 +1,212 @@
+use rand::{Rng, RngCore};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use crate::lib::search::PriorProvider;
+use crate::lib::search_problem::{HiddenState, Observation, SearchProblem};
+use crate::lib::{Rollout, RolloutPolicy, Simulator};
+
+// Plays the actions of the rollout policy until the horizon or a terminal state,
+// chance outcomes are drawn with their probabilities
+pub(crate) struct PolicySimulator<R> {
+    pub(crate) policy: R,
+}
+
+impl<P, R> Simulator<P> for PolicySimulator<R> where P: SearchProblem, R: RolloutPolicy<P> {
+    fn simulate(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32, rng: &mut dyn RngCore) -> Vec<(P::Player, f32)> {
+        self.simulate_with_actions(problem, state, horizon, discount, rng).values
+    }
+
+    fn simulate_with_actions(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32, rng: &mut dyn RngCore) -> Rollout<P> {
+        let mut scores: Vec<(P::Player, f32)> = problem.get_all_players().into_iter().map(|player| (player, 0.0)).collect();
+        let mut actions = vec![];
+        let mut discount_factor = 1.0;
+        let mut current_state = state;
+        for _ in 0..horizon {
+            if current_state.is_terminal() {
+                break;
+            }
+
+            let current_player = current_state.current_actor();
+            let action = match problem.chance_outcomes(&current_state) {
+                Some(outcomes) => outcomes.choose_weighted(rng, |(_, p)| *p).unwrap().0,
+                None => {
+                    let legal_actions = problem.get_observation(&current_state, current_player).legal_actions();
+                    self.policy.choose(problem, &current_state, &legal_actions, rng)
+                }
+            };
+            actions.push((current_player, action));
+            current_state = current_state.apply(&action);
+
+            for (player, score) in scores.iter_mut() {
+                *score += discount_factor * problem.get_observation(&current_state, *player).reward()
+            }
+
+            discount_factor *= discount;
+        }
+
+        Rollout {
+            values: scores,
+            actions,
+        }
+    }
+}
+
+// Uniformly random actions
+pub(crate) struct UniformRollout;
+
+impl<P: SearchProblem> RolloutPolicy<P> for UniformRollout {
+    fn choose(&self, _: &P, _: &P::HiddenState, actions: &[P::Action], rng: &mut dyn RngCore) -> P::Action {
+        *actions.choose(rng).unwrap()
+    }
+}
+
+// How good an action looks for the player to move, higher is better
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) trait ActionScorer<P: SearchProblem> {
+    fn score(&self, problem: &P, hidden_state: &P::HiddenState, action: &P::Action) -> f32;
+}
+
+impl<P, F> ActionScorer<P> for F
+    where
+        P: SearchProblem,
+        F: Fn(&P, &P::HiddenState, &P::Action) -> f32 {
+    fn score(&self, problem: &P, hidden_state: &P::HiddenState, action: &P::Action) -> f32 {
+        self(problem, hidden_state, action)
+    }
+}
+
+// The action with the best score (the first of them on ties), or a uniformly random one with probability epsilon
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct EpsilonGreedyRollout<H> {
+    pub(crate) epsilon: f32,
+    pub(crate) scorer: H,
+}
+
+impl<P: SearchProblem, H: ActionScorer<P>> RolloutPolicy<P> for EpsilonGreedyRollout<H> {
+    fn choose(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action], rng: &mut dyn RngCore) -> P::Action {
+        if rng.gen::<f32>() < self.epsilon {
+            return *actions.choose(rng).unwrap()
+        }
+        let mut best = actions[0];
+        let mut best_score = f32::MIN;
+        for action in actions {
+            let score = self.scorer.score(problem, hidden_state, action);
+            if score > best_score {
+                best = *action;
+                best_score = score;
+            }
+        }
+        best
+    }
+}
+
+// Actions drawn with the probabilities softmax(score / temperature), the temperature is positive
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct SoftmaxRollout<H> {
+    pub(crate) temperature: f32,
+    pub(crate) scorer: H,
+}
+
+impl<P: SearchProblem, H: ActionScorer<P>> RolloutPolicy<P> for SoftmaxRollout<H> {
+    fn choose(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action], rng: &mut dyn RngCore) -> P::Action {
+        let logits: Vec<f32> = actions
+            .iter()
+            .map(|action| self.scorer.score(problem, hidden_state, action) / self.temperature)
+            .collect();
+        actions[sample_softmax(&logits, rng)]
+    }
+}
+
+// Actions drawn from a learned policy with the probabilities proportional to prior^(1 / temperature),
+// so the policy is followed more greedily below a temperature of 1 and explored more above it
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct BoltzmannRollout<L> {
+    pub(crate) temperature: f32,
+    pub(crate) policy: L,
+}
+
+impl<P: SearchProblem, L: PriorProvider<P>> RolloutPolicy<P> for BoltzmannRollout<L> {
+    fn choose(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action], rng: &mut dyn RngCore) -> P::Action {
+        let logits: Vec<f32> = self
+            .policy
+            .priors(problem, hidden_state, actions)
+            .iter()
+            .map(|prior| prior.max(f32::MIN_POSITIVE).ln() / self.temperature)
+            .collect();
+        actions[sample_softmax(&logits, rng)]
+    }
+}
+
+// An index drawn with the probabilities softmax(logits)
+#[cfg_attr(not(test), allow(dead_code))]
+fn sample_softmax(logits: &[f32], rng: &mut dyn RngCore) -> usize {
+    let max_logit = logits.iter().copied().fold(f32::MIN, f32::max);
+    let weights: Vec<f32> = logits.iter().map(|logit| (logit - max_logit).exp()).collect();
+    WeightedIndex::new(&weights).unwrap().sample(rng)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::lib::{RolloutPolicy, Simulator};
+    use crate::lib::rollout::{BoltzmannRollout, EpsilonGreedyRollout, PolicySimulator, SoftmaxRollout, UniformRollout};
+    use crate::lib::search::PriorProvider;
+    use crate::lib::test_games::{Picked, Picks};
+
+    fn digit(_: &Picks, _: &Picked, action: &u8) -> f32 {
+        *action as f32
+    }
+
+    // Prefers the lowest digits
+    struct Descending;
+
+    impl PriorProvider<Picks> for Descending {
+        fn priors(&self, _: &Picks, _: &Picked, actions: &[u8]) -> Vec<f32> {
+            let total: f32 = actions.iter().map(|action| (4 - action) as f32).sum();
+            actions.iter().map(|action| (4 - action) as f32 / total).collect()
+        }
+    }
+
+    // How often each of the digits 1, 2 and 3 is chosen in the start state over a thousand draws
+    fn counts<R: RolloutPolicy<Picks>>(policy: &R) -> Vec<u32> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let start = Picked::start();
+        let mut counts = vec![0; 3];
+        for _ in 0..1000 {
+            counts[policy.choose(&Picks, &start, &[1, 2, 3], &mut rng) as usize - 1] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn policies() {
+        let uniform = counts(&UniformRollout);
+        assert!(uniform.iter().all(|count| *count > 280 && *count < 390));
+
+        assert_eq!(counts(&EpsilonGreedyRollout { epsilon: 0.0, scorer: digit }), vec![0, 0, 1000]);
+        // the random actions include the best one
+        let greedy = counts(&EpsilonGreedyRollout { epsilon: 0.2, scorer: digit });
+        assert!(greedy[2] > 800 && greedy[0] > 20);
+
+        let sharp = counts(&SoftmaxRollout { temperature: 0.1, scorer: digit });
+        assert!(sharp[2] > 990);
+        let flat = counts(&SoftmaxRollout { temperature: 100.0, scorer: digit });
+        assert!(flat.iter().all(|count| *count > 280 && *count < 390));
+
+        // the priors are followed at a temperature of 1, and more greedily below it
+        let followed = counts(&BoltzmannRollout { temperature: 1.0, policy: Descending });
+        assert!(followed[0] > 450 && followed[0] < 550 && followed[2] > 110 && followed[2] < 220);
+        let greedy = counts(&BoltzmannRollout { temperature: 0.2, policy: Descending });
+        assert!(greedy[0] > 800);
+    }
+
+    #[test]
+    fn policy_simulator() {
+        let simulator = PolicySimulator { policy: EpsilonGreedyRollout { epsilon: 0.0, scorer: digit } };
+        let rollout = simulator.simulate_with_actions(&Picks, Picked::start(), 10, 0.5, &mut StdRng::seed_from_u64(0));
+        // only the last pick is rewarded
+        assert_eq!(rollout.values, vec![(0, 0.25)]);
+        assert_eq!(rollout.actions, vec![(0, 3), (0, 3), (0, 3)]);
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use crate::lib::search::tree::Tree;
+
+// How much work a search is allowed to do. The search can be stopped
+// at any point, so all budgets give a usable result.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Budget {
+    Iterations(u32),
+    #[cfg_attr(not(test), allow(dead_code))]
+    Time(Duration),
+    // Nodes in the tree of the player to move at the root,
+    // only suitable when the tree can grow that large
+    #[cfg_attr(not(test), allow(dead_code))]
+    Nodes(usize),
+}
+
+// Keeps track of the budget used by a search, shared by all of its threads
+pub(crate) struct BudgetTracker {
+    budget: Budget,
+    early_termination: bool,
+    start: Instant,
+    started: AtomicU32,
+    finished: AtomicU32,
+}
+
+impl BudgetTracker {
+    pub(crate) fn new(budget: Budget, early_termination: bool) -> Self {
+        BudgetTracker {
+            budget,
+            early_termination,
+            start: Instant::now(),
+            started: AtomicU32::new(0),
+            finished: AtomicU32::new(0),
+        }
+    }
+
+    // Reserves an iteration, returns false when the budget is exhausted
+    pub(crate) fn start_iteration(&self, node_count: usize) -> bool {
+        match self.budget {
+            Budget::Iterations(iterations) => self.started.fetch_add(1, Ordering::Relaxed) < iterations,
+            Budget::Time(limit) => {
+                let within = self.start.elapsed() < limit;
+                if within {
+                    self.started.fetch_add(1, Ordering::Relaxed);
+                }
+                within
+            }
+            Budget::Nodes(limit) => {
+                let within = node_count < limit;
+                if within {
+                    self.started.fetch_add(1, Ordering::Relaxed);
+                }
+                within
+            }
+        }
+    }
+
+    pub(crate) fn finish_iteration(&self) {
+        self.finished.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finished_iterations(&self) -> u32 {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    // An upper bound on the number of iterations that can still be started,
+    // None if there is no such bound (yet)
+    fn remaining_iterations(&self) -> Option<u32> {
+        match self.budget {
+            Budget::Iterations(iterations) =>
+                Some(iterations.saturating_sub(self.started.load(Ordering::Relaxed))),
+            Budget::Time(limit) => {
+                let finished = self.finished.load(Ordering::Relaxed);
+                let elapsed = self.start.elapsed();
+                if finished == 0 || elapsed.is_zero() {
+                    return None
+                }
+                let remaining = limit.saturating_sub(elapsed);
+                // extrapolate the rate so far, with some slack as it is only an estimate
+                let estimate = 2.0 * remaining.as_secs_f64() * finished as f64 / elapsed.as_secs_f64();
+                Some(estimate.ceil() as u32)
+            }
+            // an iteration does not always add a node, so there is no bound
+            Budget::Nodes(_) => None,
+        }
+    }
+
+    // True when early termination is enabled and the most visited action at the root
+    // can no longer be overtaken by the second with the remaining budget
+    pub(crate) fn decided<L, A>(&self, tree: &Tree<L, A>) -> bool where A: PartialEq {
+        if !self.early_termination {
+            return false
+        }
+        let remaining = match self.remaining_iterations() {
+            Some(remaining) => remaining,
+            None => return false,
+        };
+        let mut best = 0;
+        let mut second = 0;
+        for edge in tree.edges(tree.root()) {
+            let edge = tree.edge(*edge);
+            if edge.is_dangling() {
+                continue
+            }
+            let visits = tree.get_statistics_lock(edge.get_target_node()).sample_count();
+            if visits > best {
+                second = best;
+                best = visits;
+            } else if visits > second {
+                second = visits;
+            }
+        }
+        tree.edges(tree.root()).len() > 1 && best - second > remaining
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::search::budget::{Budget, BudgetTracker};
+    use crate::lib::search::tree::Tree;
+
+    #[test]
+    fn iterations() {
+        let tracker = BudgetTracker::new(Budget::Iterations(2), false);
+        assert!(tracker.start_iteration(0));
+        assert!(tracker.start_iteration(0));
+        assert!(!tracker.start_iteration(0));
+    }
+
+    #[test]
+    fn nodes() {
+        let tracker = BudgetTracker::new(Budget::Nodes(10), false);
+        assert!(tracker.start_iteration(9));
+        assert!(!tracker.start_iteration(10));
+    }
+
+    #[test]
+    fn early_termination() {
+        let mut tree = Tree::new(0, vec![1, 2]);
+        let edge = tree.get_edge(tree.root(), &1);
+        let child = tree.create_child(edge, 1, vec![]);
+        tree.get_statistics_lock(child).add_sample(1.0, 5);
+
+        let tracker = BudgetTracker::new(Budget::Iterations(8), true);
+        for _ in 0..3 {
+            assert!(tracker.start_iteration(0));
+        }
+        // 5 remaining iterations could still tie
+        assert!(!tracker.decided(&tree));
+        assert!(tracker.start_iteration(0));
+        assert!(tracker.decided(&tree));
+
+        let tracker = BudgetTracker::new(Budget::Iterations(0), false);
+        assert!(!tracker.decided(&tree));
+    }
+}
@@ -0,0 +1,77 @@
+use rand::RngCore;
+use crate::lib::search::{Evaluation, Heuristic, LeafEvaluator};
+use crate::lib::search_problem::SearchProblem;
+use crate::lib::Simulator;
+
+// Evaluates leaves with the rollouts of a simulator, for mixing two simulators
+// or a simulator with a different horizon than the search
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct RolloutEvaluator<S> {
+    pub(crate) simulator: S,
+    pub(crate) horizon: u32,
+    pub(crate) discount: f32,
+}
+
+impl<P, S> LeafEvaluator<P> for RolloutEvaluator<S>
+    where
+        P: SearchProblem,
+        P::HiddenState: Clone,
+        S: Simulator<P> {
+    fn evaluate(&self, problem: &P, hidden_state: &P::HiddenState, _: &[P::Action], rng: &mut dyn RngCore) -> Evaluation<P> {
+        Evaluation {
+            values: self.simulator.simulate(problem, hidden_state.clone(), self.horizon, self.discount, rng),
+            priors: None,
+        }
+    }
+}
+
+// Evaluates leaves with a heuristic function, like the depth limit of expectimax
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct HeuristicEvaluator<H> {
+    pub(crate) heuristic: H,
+}
+
+impl<P: SearchProblem, H: Heuristic<P>> LeafEvaluator<P> for HeuristicEvaluator<H> {
+    fn evaluate(&self, problem: &P, hidden_state: &P::HiddenState, _: &[P::Action], _: &mut dyn RngCore) -> Evaluation<P> {
+        Evaluation {
+            values: self.heuristic.evaluate(problem, hidden_state),
+            priors: None,
+        }
+    }
+}
+
+// A network predicting the values of all the players after a state and the logits of the given actions there
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) trait Network<P: SearchProblem> {
+    fn predict(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action]) -> (Vec<(P::Player, f32)>, Vec<f32>);
+}
+
+impl<P, F> Network<P> for F
+    where
+        P: SearchProblem,
+        F: Fn(&P, &P::HiddenState, &[P::Action]) -> (Vec<(P::Player, f32)>, Vec<f32>) {
+    fn predict(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action]) -> (Vec<(P::Player, f32)>, Vec<f32>) {
+        self(problem, hidden_state, actions)
+    }
+}
+
+// Evaluates leaves with a network, the priors are the softmax of its logits
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct NetworkEvaluator<N> {
+    pub(crate) network: N,
+}
+
+impl<P: SearchProblem, N: Network<P>> LeafEvaluator<P> for NetworkEvaluator<N> {
+    fn evaluate(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action], _: &mut dyn RngCore) -> Evaluation<P> {
+        let (values, logits) = self.network.predict(problem, hidden_state, actions);
+        let priors = if actions.is_empty() {
+            None
+        } else {
+            let max_logit = logits.iter().copied().fold(f32::MIN, f32::max);
+            let exponentials: Vec<f32> = logits.iter().map(|logit| (logit - max_logit).exp()).collect();
+            let total: f32 = exponentials.iter().sum();
+            Some(exponentials.iter().map(|e| e / total).collect())
+        };
+        Evaluation { values, priors }
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use crate::lib::search::Heuristic;
+use crate::lib::search::searcher::{ActionStatistics, SearchResult};
+use crate::lib::search_problem::{HiddenState, Observation, SearchProblem};
+use crate::lib::utils::{index_for_player, reward_for_all_players};
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct ExpectimaxConfig<P, H> {
+    pub(crate) search_problem: P,
+    pub(crate) heuristic: H,
+    // in plies, chance events included
+    pub(crate) depth: u32,
+    pub(crate) discount: f32,
+}
+
+// How much work an expectimax search did
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct ExpectimaxStatistics {
+    // states searched below the depth limit, each of them is cached
+    pub(crate) evaluated: u32,
+    // states whose value was found in the transposition cache instead
+    pub(crate) cache_hits: u32,
+}
+
+// Depth limited expectimax: every player maximises their own return (so this is
+// expectiminimax for zero sum games) and chance nodes average over their outcomes.
+// Values are discounted returns like in the trees, so the result compares with MCTS.
+// Nothing is visited, so the visit counts are 0 and the probabilities pick the best action.
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) fn expectimax<P, H>(config: &ExpectimaxConfig<P, H>, hidden_state: &P::HiddenState) -> SearchResult<P::Action>
+    where
+        P: SearchProblem,
+        P::HiddenState: Clone + Eq + Hash,
+        H: Heuristic<P> {
+    expectimax_with_statistics(config, hidden_state).0
+}
+
+// Like expectimax, also reporting how much the transposition cache saved
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) fn expectimax_with_statistics<P, H>(
+    config: &ExpectimaxConfig<P, H>,
+    hidden_state: &P::HiddenState,
+) -> (SearchResult<P::Action>, ExpectimaxStatistics)
+    where
+        P: SearchProblem,
+        P::HiddenState: Clone + Eq + Hash,
+        H: Heuristic<P> {
+    let mut search = Expectimax {
+        config,
+        players: config.search_problem.get_all_players(),
+        cache: HashMap::new(),
+        statistics: ExpectimaxStatistics::default(),
+    };
+
+    let root_player = hidden_state.current_actor();
+    let outcomes = config.search_problem.chance_outcomes(hidden_state);
+    let actions: Vec<(P::Action, f32)> = match &outcomes {
+        Some(outcomes) => outcomes.clone(),
+        None => config.search_problem
+            .get_observation(hidden_state, root_player)
+            .legal_actions()
+            .into_iter()
+            .map(|action| (action, 0.0))
+            .collect(),
+    };
+
+    let mut result = vec![];
+    for (action, probability) in actions {
+        let value = if config.depth == 0 {
+            0.0
+        } else {
+            index_for_player(&search.action_value(hidden_state, &action, config.depth), &root_player)
+        };
+        result.push(ActionStatistics {
+            action,
+            visit_count: 0,
+            value,
+            probability,
+            proven: None,
+        });
+    }
+
+    // chance picks the most likely outcome, a player its best action
+    let best = if outcomes.is_some() {
+        result.iter().max_by(|a, b| a.probability.total_cmp(&b.probability))
+    } else {
+        result.iter().max_by(|a, b| a.value.total_cmp(&b.value))
+    };
+    let best_action = best.map(|a| a.action);
+    if outcomes.is_none() {
+        for action in result.iter_mut() {
+            action.probability = if Some(action.action) == best_action { 1.0 } else { 0.0 };
+        }
+    }
+
+    let result = SearchResult {
+        best_action,
+        actions: result,
+    };
+    (result, search.statistics)
+}
+
+// Returns of all the players
+#[cfg_attr(not(test), allow(dead_code))]
+type Values<Player> = Vec<(Player, f32)>;
+
+#[cfg_attr(not(test), allow(dead_code))]
+struct Expectimax<'a, P: SearchProblem, H> {
+    config: &'a ExpectimaxConfig<P, H>,
+    players: Vec<P::Player>,
+    // transposition cache, values of states by the remaining depth
+    cache: HashMap<(P::HiddenState, u32), Values<P::Player>>,
+    statistics: ExpectimaxStatistics,
+}
+
+impl<'a, P, H> Expectimax<'a, P, H>
+    where
+        P: SearchProblem,
+        P::HiddenState: Clone + Eq + Hash,
+        H: Heuristic<P> {
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn state_value(&mut self, hidden_state: &P::HiddenState, depth: u32) -> Vec<(P::Player, f32)> {
+        if hidden_state.is_terminal() {
+            return self.players.iter().map(|player| (*player, 0.0)).collect()
+        }
+        if depth == 0 {
+            let values = self.config.heuristic.evaluate(&self.config.search_problem, hidden_state);
+            return self.players.iter().map(|player| (*player, index_for_player(&values, player))).collect()
+        }
+        let key = (hidden_state.clone(), depth);
+        if let Some(values) = self.cache.get(&key) {
+            self.statistics.cache_hits += 1;
+            return values.clone()
+        }
+
+        let values = match self.config.search_problem.chance_outcomes(hidden_state) {
+            Some(outcomes) => {
+                let mut values: Vec<(P::Player, f32)> = self.players.iter().map(|player| (*player, 0.0)).collect();
+                let total_probability: f32 = outcomes.iter().map(|(_, probability)| probability).sum();
+                for (outcome, probability) in outcomes {
+                    let outcome_values = self.action_value(hidden_state, &outcome, depth);
+                    for (player, value) in values.iter_mut() {
+                        *value += probability / total_probability * index_for_player(&outcome_values, player);
+                    }
+                }
+                values
+            }
+            None => {
+                let player = hidden_state.current_actor();
+                let actions = self.config.search_problem.get_observation(hidden_state, player).legal_actions();
+                let mut best: Option<Vec<(P::Player, f32)>> = None;
+                for action in actions {
+                    let action_values = self.action_value(hidden_state, &action, depth);
+                    let better = match &best {
+                        Some(best) => index_for_player(&action_values, &player) > index_for_player(best, &player),
+                        None => true,
+                    };
+                    if better {
+                        best = Some(action_values);
+                    }
+                }
+                best.unwrap_or_else(|| self.players.iter().map(|player| (*player, 0.0)).collect())
+            }
+        };
+        self.statistics.evaluated += 1;
+        self.cache.insert(key, values.clone());
+        values
+    }
+
+    // The reward of taking the action + the discounted value of the resulting state
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn action_value(&mut self, hidden_state: &P::HiddenState, action: &P::Action, depth: u32) -> Vec<(P::Player, f32)> {
+        let next_state = hidden_state.apply(action);
+        let rewards = reward_for_all_players(&self.config.search_problem, &next_state);
+        let next_values = self.state_value(&next_state, depth - 1);
+        self.players
+            .iter()
+            .map(|player| (*player, index_for_player(&rewards, player) + self.config.discount * index_for_player(&next_values, player)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::search::expectimax::{expectimax, expectimax_with_statistics, ExpectimaxConfig};
+    use crate::lib::tzf8::{Action, Board, Player, TwoZeroFourEight};
+    use crate::lib::tzf8::test::board1;
+
+    #[test]
+    fn depth_limit() {
+        let config = ExpectimaxConfig {
+            search_problem: TwoZeroFourEight{},
+            heuristic: |_: &TwoZeroFourEight, _: &Board| vec![],
+            depth: 1,
+            discount: 1.0,
+        };
+        // merging the 2s in the first column and the 4s in the last one
+        let result = expectimax(&config, &board1());
+        assert!(matches!(result.best_action, Some(Action::Up) | Some(Action::Down)));
+        assert_eq!(result.get(&result.best_action.unwrap()).unwrap().value, 12.0);
+        assert_eq!(result.get(&result.best_action.unwrap()).unwrap().probability, 1.0);
+    }
+
+    #[test]
+    fn transpositions() {
+        let config = ExpectimaxConfig {
+            search_problem: TwoZeroFourEight{},
+            heuristic: |_: &TwoZeroFourEight, b: &Board| {
+                let empty = b.cells.iter().flatten().filter(|c| **c == 0).count();
+                vec![(Player::Agent, empty as f32)]
+            },
+            depth: 4,
+            discount: 1.0,
+        };
+        let (result, statistics) = expectimax_with_statistics(&config, &board1());
+        assert_eq!(result.actions.len(), 4);
+        assert!(result.best_action.is_some());
+        // different moves and placements lead to the same states,
+        // which are found in the cache instead of being searched again
+        assert!(statistics.cache_hits > 0);
+
+        // with a single placement, no two paths lead to the same state
+        let shallow = ExpectimaxConfig { depth: 3, ..config };
+        let (_, shallow) = expectimax_with_statistics(&shallow, &board1());
+        assert_eq!(shallow.cache_hits, 0);
+        assert!(shallow.evaluated < statistics.evaluated);
+    }
+}
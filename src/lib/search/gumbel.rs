@@ -0,0 +1,253 @@
+use rand::{Rng, RngCore};
+use crate::lib::search::TreePolicy;
+use crate::lib::search::budget::Budget;
+use crate::lib::search::mcts::{MctsConfig, once_with_root_action};
+use crate::lib::search::parallel::run;
+use crate::lib::search::searcher::{result, SearchResult};
+use crate::lib::search::tree::{SharedTree, Tree};
+use crate::lib::search_problem::{HiddenState, SearchProblem};
+use crate::lib::utils::index_for_player;
+use crate::lib::Simulator;
+
+// Root action selection from "Policy improvement by planning with Gumbel" (Gumbel MuZero).
+// The actions considered at the root are sampled without replacement by their logits perturbed with
+// Gumbel noise, and the simulations are spread over them with sequential halving: in every phase each
+// remaining action gets the same number of simulations, then the worse half is dropped, by
+// gumbel + logit + sigma(q) with sigma(q) = (c_visit + most visits) * c_scale * normalized q.
+// The action left with the best score is played, and the improved policy
+// softmax(logits + sigma(completed q)) is the policy target, where unvisited actions get the mixed value
+// of the root. Both improve on the prior policy in expectation even with few simulations.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct Gumbel {
+    pub(crate) considered_actions: usize,
+    pub(crate) c_visit: f32,
+    pub(crate) c_scale: f32,
+}
+
+impl Gumbel {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn new(considered_actions: usize, c_visit: f32, c_scale: f32) -> Self {
+        Gumbel {
+            considered_actions,
+            c_visit,
+            c_scale,
+        }
+    }
+}
+
+// Searches the given number of simulations with Gumbel root selection, sequentially.
+// The probabilities of the result are the improved policy.
+// Chance nodes and simultaneous moves at the root are searched as usual.
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) fn gumbel_search<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: &P::HiddenState,
+    trees: &mut [SharedTree<P::Player, P::Action>],
+    gumbel: &Gumbel,
+    simulations: u32,
+    rng: &mut dyn RngCore,
+) -> SearchResult<P::Action>
+    where
+        P: SearchProblem + Sync,
+        P::HiddenState: Clone + Send + Sync,
+        P::Player: Send + Sync,
+        P::Action: Send + Sync,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState> + Sync,
+        S: Simulator<P> + Sync {
+    let root_player = hidden_state.current_actor();
+    let root_tree = &trees[config.player_index(root_player)];
+    let chance = {
+        let tree = root_tree.read();
+        tree.node(tree.root()).is_chance()
+    };
+    if chance || config.simultaneous_moves(hidden_state).is_some() {
+        run(config, hidden_state, trees, Budget::Iterations(simulations), rng);
+        return result(config, hidden_state, trees)
+    }
+
+    config.set_root_priors(hidden_state, trees, rng);
+    let (actions, logits) = {
+        let tree = root_tree.read();
+        let edges = tree.edges(tree.root());
+        let actions: Vec<P::Action> = edges.iter().map(|e| tree.edge(*e).label).collect();
+        let logits: Vec<f32> = edges.iter().map(|e| tree.edge(*e).get_prior().max(f32::MIN_POSITIVE).ln()).collect();
+        (actions, logits)
+    };
+    if actions.is_empty() {
+        return result(config, hidden_state, trees)
+    }
+    let gumbels: Vec<f32> = actions.iter().map(|_| -(-rng.gen_range(f32::EPSILON..1.0f32).ln()).ln()).collect();
+    let root_value = index_for_player(&config.evaluate(hidden_state.clone(), rng), &root_player);
+
+    // Gumbel-Top-k: the actions with the best perturbed logits
+    let mut remaining: Vec<usize> = (0..actions.len()).collect();
+    remaining.sort_by(|a, b| (gumbels[*b] + logits[*b]).total_cmp(&(gumbels[*a] + logits[*a])));
+    remaining.truncate(gumbel.considered_actions.clamp(1, actions.len()));
+
+    let phases = (remaining.len() as f32).log2().ceil().max(1.0) as u32;
+    let mut used = 0;
+    for phase in 0..phases {
+        let last = phase + 1 == phases;
+        let per_action = if last {
+            (simulations - used).div_ceil(remaining.len() as u32)
+        } else {
+            (simulations / (phases * remaining.len() as u32)).max(1)
+        };
+        'phase: for _ in 0..per_action {
+            for ix in remaining.iter() {
+                if used == simulations {
+                    break 'phase
+                }
+                once_with_root_action(config, hidden_state.clone(), trees, actions[*ix], rng);
+                used += 1;
+            }
+        }
+        if !last {
+            let scores = scores(gumbel, &root_tree.read(), &gumbels, &logits, root_value);
+            remaining.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+            remaining.truncate(remaining.len().div_ceil(2));
+        }
+    }
+
+    let tree = root_tree.read();
+    let scores = scores(gumbel, &tree, &gumbels, &logits, root_value);
+    let best = remaining.iter().copied().max_by(|a, b| scores[*a].total_cmp(&scores[*b])).unwrap();
+    let policy = improved_policy(gumbel, &tree, &logits, root_value);
+    let mut search_result = SearchResult::from_tree(&tree);
+    for (statistics, probability) in search_result.actions.iter_mut().zip(policy) {
+        statistics.probability = probability;
+    }
+    search_result.best_action = Some(actions[best]);
+    search_result
+}
+
+// gumbel + logit + sigma(completed q) of every root action
+#[cfg_attr(not(test), allow(dead_code))]
+fn scores<L, A: PartialEq>(gumbel: &Gumbel, tree: &Tree<L, A>, gumbels: &[f32], logits: &[f32], root_value: f32) -> Vec<f32> {
+    let sigma = sigma(gumbel, tree, root_value);
+    gumbels.iter().zip(logits).zip(sigma).map(|((g, logit), sigma)| g + logit + sigma).collect()
+}
+
+// softmax(logits + sigma(completed q)) over the root actions
+#[cfg_attr(not(test), allow(dead_code))]
+fn improved_policy<L, A: PartialEq>(gumbel: &Gumbel, tree: &Tree<L, A>, logits: &[f32], root_value: f32) -> Vec<f32> {
+    let sigma = sigma(gumbel, tree, root_value);
+    let scores: Vec<f32> = logits.iter().zip(sigma).map(|(logit, sigma)| logit + sigma).collect();
+    let max_score = scores.iter().copied().fold(f32::MIN, f32::max);
+    let exponentials: Vec<f32> = scores.iter().map(|score| (score - max_score).exp()).collect();
+    let total: f32 = exponentials.iter().sum();
+    exponentials.iter().map(|e| e / total).collect()
+}
+
+// (c_visit + most visits) * c_scale * the normalized completed q of every root action.
+// Unvisited actions get the mixed value, the value of the root mixed with the values of the visited
+// actions weighted by their priors.
+#[cfg_attr(not(test), allow(dead_code))]
+fn sigma<L, A: PartialEq>(gumbel: &Gumbel, tree: &Tree<L, A>, root_value: f32) -> Vec<f32> {
+    let root = tree.root();
+    let children: Vec<(f32, u32, f32)> = tree
+        .edges(root)
+        .iter()
+        .map(|e| {
+            let edge = tree.edge(*e);
+            if edge.is_dangling() {
+                (edge.get_prior(), 0, 0.0)
+            } else {
+                let stats = tree.get_statistics_lock(edge.get_target_node());
+                (edge.get_prior(), stats.sample_count(), stats.expected_sample())
+            }
+        })
+        .collect();
+    let total_visits: u32 = children.iter().map(|(_, visits, _)| visits).sum();
+    let most_visits = children.iter().map(|(_, visits, _)| *visits).max().unwrap_or(0);
+    let visited_prior: f32 = children.iter().filter(|(_, visits, _)| *visits > 0).map(|(prior, _, _)| prior).sum();
+    let mixed_value = if visited_prior > 0.0 {
+        let weighted: f32 = children.iter().filter(|(_, visits, _)| *visits > 0).map(|(prior, _, value)| prior * value).sum();
+        (root_value + total_visits as f32 * weighted / visited_prior) / (1 + total_visits) as f32
+    } else {
+        root_value
+    };
+
+    let min_max_stats = tree.get_min_max_stats();
+    let scale = (gumbel.c_visit + most_visits as f32) * gumbel.c_scale;
+    children
+        .iter()
+        .map(|(_, visits, value)| {
+            let completed = if *visits > 0 { *value } else { mixed_value };
+            scale * min_max_stats.normalize(completed)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::search::gumbel::{Gumbel, gumbel_search};
+    use crate::lib::search::mcts::{initialise, MctsConfig};
+    use crate::lib::search::parallel::Parallelism;
+    use crate::lib::search::policy::UctPolicy;
+    use crate::lib::search::searcher::Searcher;
+    use crate::lib::search_problem::HiddenState;
+    use crate::lib::test_games::{Bandit, Pull};
+    use crate::lib::tzf8::Player;
+    use crate::lib::tzf8::test::{board1, config as tzf8_config};
+    use crate::lib::utils::RandomSimulator;
+
+    fn config() -> MctsConfig<UctPolicy<u8>, Bandit, RandomSimulator> {
+        MctsConfig::new(Bandit, vec![0], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+    }
+
+    #[test]
+    fn sequential_halving() {
+        let config = config();
+        let start = Pull { arm: None };
+
+        // all the arms are considered, the best one survives the halving
+        let mut trees = initialise(&config.search_problem, &start);
+        let result = gumbel_search(&config, &start, &mut trees, &Gumbel::new(5, 50.0, 1.0), 16, &mut config.rng());
+        assert_eq!(result.best_action, Some(4));
+        assert!(result.get(&4).unwrap().probability > 0.9);
+
+        // 4 arms in 2 phases: one simulation each, then the other 4 on the best 2
+        let mut trees = initialise(&config.search_problem, &start);
+        let result = gumbel_search(&config, &start, &mut trees, &Gumbel::new(4, 50.0, 1.0), 8, &mut config.rng());
+        let mut visits: Vec<u32> = result.actions.iter().map(|a| a.visit_count).collect();
+        visits.sort();
+        assert_eq!(visits, vec![0, 1, 1, 3, 3]);
+        // the unvisited arm still gets some of the improved policy
+        let total: f32 = result.actions.iter().map(|a| a.probability).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        assert!(result.actions.iter().all(|a| a.probability > 0.0));
+    }
+
+    #[test]
+    fn searcher() {
+        let gumbel = Gumbel::new(4, 50.0, 1.0);
+        let mut searcher = Searcher::new(tzf8_config(Parallelism::Sequential), board1());
+        let result = searcher.gumbel_search(&gumbel, 32);
+        let total: f32 = result.actions.iter().map(|a| a.probability).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        let action = result.best_action.unwrap();
+        let visits = result.get(&action).unwrap().visit_count;
+        assert!(visits > 0);
+
+        // the tree of the played action is kept, and the placements at the chance root are searched as usual
+        searcher.advance(&action);
+        let result = searcher.gumbel_search(&gumbel, 32);
+        let place = result.best_action.unwrap();
+        let visits = result.get(&place).unwrap().visit_count;
+        assert!(visits > 1);
+
+        searcher.advance(&place);
+        assert_eq!(searcher.hidden_state().current_actor(), Player::Agent);
+        let root_visits = |searcher: &Searcher<_, _, _>| {
+            let tree = searcher.trees()[1].read();
+            let visits = tree.get_statistics_lock(tree.root()).sample_count();
+            visits
+        };
+        assert_eq!(root_visits(&searcher), visits);
+        let result = searcher.gumbel_search(&gumbel, 16);
+        assert!(result.best_action.is_some());
+        assert_eq!(root_visits(&searcher), visits + 16);
+    }
+}
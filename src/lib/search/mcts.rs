@@ -1,9 +1,14 @@
-use std::ptr::null;
-use crate::lib::search::{EdgeLabel, TreePolicy};
-use crate::lib::search_problem::{HiddenState, Observation, SearchProblem};
-use crate::lib::search::tree::{Node, Edge};
-use crate::lib::Simulator;
-use crate::lib::utils::{index_for_player, reward_for_all_players};
+use std::borrow::Cow;
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use crate::lib::search::{ActionProposal, ActionSampler, Determinizer, LeafEvaluator, PriorProvider, TreePolicy};
+use crate::lib::search::parallel::Parallelism;
+use crate::lib::search::simultaneous::DecoupledPolicy;
+use crate::lib::search_problem::{HiddenState, Observation, SearchProblem, SimultaneousMoves};
+use crate::lib::search::tree::{EdgeId, NodeId, Proven, SharedTree, Tree};
+use crate::lib::{Rollout, Simulator};
+use crate::lib::utils::{index_for_player, reward_for_all_players, sample_dirichlet};
 
 pub(crate) struct MctsConfig<T, P: SearchProblem, S> {
     pub(crate) search_problem: P,
@@ -12,6 +17,163 @@ pub(crate) struct MctsConfig<T, P: SearchProblem, S> {
     pub(crate) simulator: S,
     pub(crate) discount: f32,
     pub(crate) horizon: u32,
+    pub(crate) parallelism: Parallelism,
+    // stop searching once the best action at the root cannot change anymore
+    pub(crate) early_termination: bool,
+    pub(crate) expansion: Expansion<P>,
+    pub(crate) multi_player: MultiPlayer<P>,
+    pub(crate) statistics: Statistics,
+    // Seeds the random numbers of the search, so that a sequential search with the same seed and an
+    // iteration or node budget builds the same trees. Drawn from entropy without one.
+    pub(crate) seed: Option<u64>,
+    // Evaluates leaves in place of the simulator or mixed with it, rollouts only without one
+    pub(crate) leaf_evaluation: Option<LeafEvaluation<P>>,
+}
+
+// Which edges the nodes get and with which priors
+pub(crate) struct Expansion<P: SearchProblem> {
+    // priors are uniform without a provider
+    pub(crate) prior_provider: Option<Box<dyn PriorProvider<P> + Send + Sync>>,
+    // Progressive widening: nodes get edges as they are visited instead of one for every legal action,
+    // in the order of the priors or as the sampler proposes them
+    pub(crate) widening: Option<Widening>,
+    // Double progressive widening: chance nodes get outcomes as they are sampled
+    pub(crate) outcome_widening: Option<Widening>,
+    // Proposes the actions to add under progressive widening, the legal actions are used without one
+    pub(crate) action_sampler: Option<Box<dyn ActionSampler<P> + Send + Sync>>,
+    // Sampled MuZero: nodes are expanded with the actions drawn from the proposal instead of the legal actions,
+    // with priors corrected for the sampling. Not applied with a determinizer, under progressive widening
+    // or at simultaneous moves.
+    pub(crate) action_proposal: Option<Box<dyn ActionProposal<P> + Send + Sync>>,
+    // Exploration noise mixed into the priors of the root, for self-play
+    pub(crate) root_noise: Option<RootNoise>,
+}
+
+impl<P: SearchProblem> Default for Expansion<P> {
+    // All the legal actions with uniform priors
+    fn default() -> Self {
+        Expansion {
+            prior_provider: None,
+            widening: None,
+            outcome_widening: None,
+            action_sampler: None,
+            action_proposal: None,
+            root_noise: None,
+        }
+    }
+}
+
+// How the other players are searched in games that are not about a single player with perfect information
+pub(crate) struct MultiPlayer<P: SearchProblem> {
+    pub(crate) strategy: MultiPlayerStrategy,
+    // Information set search: every iteration follows a determinization sampled for the player
+    // to move at the root, the trees of the players only see what get_visible_action shows them
+    pub(crate) determinizer: Option<Box<dyn Determinizer<P> + Send + Sync>>,
+    // Selection at simultaneous moves, where every player picks its own action
+    pub(crate) decoupled_policy: DecoupledPolicy,
+}
+
+impl<P: SearchProblem> Default for MultiPlayer<P> {
+    // Every player for itself, with perfect information
+    fn default() -> Self {
+        MultiPlayer {
+            strategy: MultiPlayerStrategy::MaxN,
+            determinizer: None,
+            decoupled_policy: DecoupledPolicy::Uct { exploration_constant: 1.0 },
+        }
+    }
+}
+
+// What the nodes keep track of besides their visits and values
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Statistics {
+    // MCTS-Solver: prove the exact values of subtrees that are searched to the end, and stop
+    // searching them. Only sound for perfect information, so it is off with a determinizer.
+    pub(crate) solver: bool,
+    // Keep all-moves-as-first statistics for RAVE, from the actions played later in the tree
+    // and in the rollout, if the simulator returns them
+    pub(crate) amaf: bool,
+}
+
+// What the players are assumed to be after in games with more than two players
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum MultiPlayerStrategy {
+    // every player maximises its own return
+    MaxN,
+    // all the opponents minimise the return of the player to move at the root
+    #[cfg_attr(not(test), allow(dead_code))]
+    Paranoid,
+    // like paranoid, but only one opponent moves between two moves of the root player,
+    // the others pass (needs SearchProblem::pass_action, otherwise it is paranoid)
+    #[cfg_attr(not(test), allow(dead_code))]
+    BestReply,
+}
+
+// A node selected n times has ceil(coefficient * n^exponent) edges (at least one).
+// Not applied with a determinizer, where the legal actions of every determinization are added,
+// nor at simultaneous moves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Widening {
+    pub(crate) coefficient: f32,
+    pub(crate) exponent: f32,
+}
+
+impl Widening {
+    pub(crate) fn edges(&self, visits: u32) -> usize {
+        (self.coefficient * (visits.max(1) as f32).powf(self.exponent)).ceil().max(1.0) as usize
+    }
+}
+
+// Dirichlet noise mixed into the priors of the root at the start of every search:
+// (1 - fraction) * prior + fraction * noise, with the noise drawn from Dir(alpha).
+// AlphaZero uses a fraction of 0.25 and alpha around 10 / the typical number of legal actions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct RootNoise {
+    pub(crate) alpha: f32,
+    pub(crate) fraction: f32,
+}
+
+impl RootNoise {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn new(alpha: f32, fraction: f32) -> Self {
+        RootNoise { alpha, fraction }
+    }
+}
+
+// Values of the leaves from the evaluator mixed with the rollouts like in AlphaGo:
+// (1 - lambda) * evaluation + lambda * rollout, there are no rollouts with a lambda of 0.
+// Priors returned by the evaluator replace the priors of the roots and of new nodes that are expanded
+// with all their legal actions, in the tree of the player to move.
+pub(crate) struct LeafEvaluation<P: SearchProblem> {
+    pub(crate) evaluator: Box<dyn LeafEvaluator<P> + Send + Sync>,
+    pub(crate) lambda: f32,
+}
+
+// What select keeps track of on the way down
+struct Descent<P: SearchProblem> {
+    root_player: P::Player,
+    // if an opponent has moved since the last move of the root player
+    opponent_moved: bool,
+    // followed at the root instead of the tree policy, taken once used
+    root_action: Option<P::Action>,
+}
+
+// The rollouts backed up together in one tree, on the way up
+struct Backup<P: SearchProblem> {
+    root_player: P::Player,
+    // the returns of every rollout after the current node, and the actions played after it in the rollout
+    samples: Vec<Vec<(P::Player, f32)>>,
+    later: Vec<Vec<(P::Player, P::Action)>>,
+}
+
+// Where select stopped, one node in every tree
+pub(crate) struct Trajectory<P: SearchProblem> {
+    pub(crate) hidden_state: P::HiddenState,
+    pub(crate) nodes: Vec<NodeId>,
+    // rewards of all the players received on every step from the roots
+    pub(crate) rewards: Vec<Vec<(P::Player, f32)>>,
+    // the exact returns after the last node, when it is solved
+    pub(crate) proven: Option<Vec<(P::Player, f32)>>,
 }
 
 
@@ -19,21 +181,61 @@ impl<T, P, S> MctsConfig<T, P, S>
     where
         P: SearchProblem,
     // Not storing state in tree nodes yet, only current player
-        T: TreePolicy<Node<P::Player, P::Action>, P::HiddenState, Edge<P::Player, P::Action>>,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState>,
         S: Simulator<P> {
 
-    fn select<'a>(
+    // A sequential search with rollouts and uniform priors, the other fields are set with struct update syntax
+    pub(crate) fn new(search_problem: P, players: Vec<P::Player>, tree_policy: T, simulator: S) -> Self {
+        MctsConfig {
+            search_problem,
+            players,
+            tree_policy,
+            simulator,
+            discount: 1.0,
+            horizon: 100,
+            parallelism: Parallelism::Sequential,
+            early_termination: false,
+            expansion: Expansion::default(),
+            multi_player: MultiPlayer::default(),
+            statistics: Statistics::default(),
+            seed: None,
+            leaf_evaluation: None,
+        }
+    }
+
+    pub(crate) fn select(
         &self,
-        mut hidden_state: P::HiddenState,
-        mut nodes: Vec<&'a Node<P::Player, P::Action>>,
-    ) -> (
-        P::HiddenState, // The resulting state
-        Vec<&'a Node<P::Player, P::Action>>
-    ) {
-        'outer: for _ in 0..self.horizon {
+        hidden_state: P::HiddenState,
+        trees: &[SharedTree<P::Player, P::Action>],
+        virtual_loss: f32,
+        rng: &mut dyn RngCore,
+    ) -> Trajectory<P> {
+        self.select_with_root_action(hidden_state, trees, None, virtual_loss, rng)
+    }
+
+    // Like select, following the given action at the root instead of the tree policy
+    pub(crate) fn select_with_root_action(
+        &self,
+        hidden_state: P::HiddenState,
+        trees: &[SharedTree<P::Player, P::Action>],
+        root_action: Option<P::Action>,
+        virtual_loss: f32,
+        rng: &mut dyn RngCore,
+    ) -> Trajectory<P> {
+        let mut hidden_state = self.determinize(hidden_state, rng);
+        let mut nodes: Vec<NodeId> = trees.iter().map(|tree| tree.read().root()).collect();
+        let mut rewards = vec![];
+        let mut descent = Descent {
+            root_player: hidden_state.current_actor(),
+            opponent_moved: false,
+            root_action,
+        };
+        let mut proven = None;
+        Self::add_virtual_loss(trees, &nodes, virtual_loss);
+        for _ in 0..self.horizon {
 
-            for node in nodes.iter() {
-                node.get_statistics_lock().increment_select_count();
+            for (tree, node) in trees.iter().zip(nodes.iter()) {
+                tree.read().get_statistics_lock(*node).increment_select_count();
             }
 
             // if any of the trees reaches a terminal node, all trees are in terminal
@@ -47,85 +249,888 @@ impl<T, P, S> MctsConfig<T, P, S>
             }
 
             let current_player = hidden_state.current_actor();
-            let current_node = nodes[self.player_index(current_player)];
-            let selected_edge =  self.tree_policy.select_edge(current_node, &hidden_state);
+            let current_index = self.player_index(current_player);
+            proven = self.proven_returns(&trees[current_index], nodes[current_index]);
+            if proven.is_some() {
+                break;
+            }
+            let legal_edges = self.add_legal_edges(&trees[current_index], nodes[current_index], &hidden_state);
+            let widening_limit = self.widen_node(&trees[current_index], nodes[current_index], &hidden_state, rng);
+            let (selected_action, trajectory_terminal) = {
+                let current_tree = trees[current_index].read();
+                let current_node = nodes[current_index];
+                let edges = Self::candidate_edges(&current_tree, current_node, &legal_edges, widening_limit);
+                let selected_edge = self.select_edge(&current_tree, current_node, &hidden_state, &edges, &mut descent, rng);
+                let edge = current_tree.edge(selected_edge);
+                (edge.label, edge.is_dangling())
+            };
+
+            let edges = self.descend(trees, &nodes, &hidden_state, &selected_action);
+            hidden_state = hidden_state.apply(&selected_action);
+            let step_rewards = reward_for_all_players(&self.search_problem, &hidden_state);
+            // edges are labelled with the reward of the player that chose them
+            let action_reward = index_for_player(&step_rewards, &current_player);
+            rewards.push(step_rewards);
 
-            let selected_action = &selected_edge.label;
+            nodes = self.expand_edges(trees, edges, &hidden_state, action_reward, rng);
+            Self::add_virtual_loss(trees, &nodes, virtual_loss);
+            Self::count_available(&trees[current_index], &legal_edges);
 
-            let mut edges = vec![];
-            for (player, node) in self.search_problem.get_all_players().iter().zip(nodes.iter()) {
-                edges.push(
-                    node.get_edge(
-                        &self
-                            .search_problem
-                            .get_visible_action(
-                                &hidden_state,
-                                selected_action,
-                                player
-                            )
-                    )
-                );
+            if trajectory_terminal {
+                break
             }
+        }
+        Trajectory {
+            hidden_state,
+            nodes,
+            rewards,
+            proven: proven.map(|after| self.players.iter().copied().zip(after).collect()),
+        }
+    }
 
-            hidden_state = hidden_state.apply(selected_action);
+    // MCTS-Solver: the exact returns after the node, once it is proven
+    fn proven_returns(&self, tree: &SharedTree<P::Player, P::Action>, node: NodeId) -> Option<Vec<f32>> {
+        if !self.statistics.solver {
+            return None
+        }
+        let tree = tree.read();
+        let stats = tree.get_statistics_lock(node);
+        stats.proven().map(|solved| solved.after.clone())
+    }
 
-            nodes = vec![];
-            let trajectory_terminal = selected_edge.is_dangling();
+    // The edges the tree policy may pick at the node: the legal ones (all of them without a determinizer),
+    // only the best ones while the node is widened
+    fn candidate_edges<'a>(
+        tree: &'a Tree<P::Player, P::Action>,
+        node: NodeId,
+        legal_edges: &'a [EdgeId],
+        widening_limit: Option<usize>,
+    ) -> Cow<'a, [EdgeId]> {
+        let edges = if legal_edges.is_empty() { tree.edges(node) } else { legal_edges };
+        match widening_limit {
+            // chance nodes created with all their outcomes sample from all of them,
+            // keeping the likeliest would bias their value
+            Some(limit) if edges.len() > limit && !tree.node(node).is_chance() => Cow::Owned(Self::best_edges(tree, edges, limit)),
+            _ => Cow::Borrowed(edges),
+        }
+    }
 
-            for edge in edges.iter() {
-                // only when the edge on the player to move's tree is dangling,
-                // do we need to terminate the trajectory
+    // Picks the edge followed from the node of the player to move: the given root action,
+    // the pass of an opponent that has to wait under Best-Reply, a joint action at simultaneous moves,
+    // or else the choice of the tree policy among the candidate edges
+    fn select_edge(
+        &self,
+        tree: &Tree<P::Player, P::Action>,
+        node: NodeId,
+        hidden_state: &P::HiddenState,
+        edges: &[EdgeId],
+        descent: &mut Descent<P>,
+        rng: &mut dyn RngCore,
+    ) -> EdgeId {
+        let current_player = hidden_state.current_actor();
+        let opponent = current_player != descent.root_player && !tree.node(node).is_chance();
+        let pass_action = match self.multi_player.strategy {
+            MultiPlayerStrategy::BestReply if opponent => self.search_problem.pass_action(hidden_state),
+            _ => None,
+        };
+        let forced_pass = match pass_action {
+            Some(pass_action) if descent.opponent_moved => tree.find_edge(node, &pass_action),
+            _ => None,
+        };
+        let forced_root = descent.root_action.take().and_then(|action| tree.find_edge(node, &action));
+        let forced = forced_root.or(forced_pass);
+        let selected_edge = match (forced, self.simultaneous_moves(hidden_state)) {
+            (Some(edge), _) => edge,
+            (None, Some(moves)) => self.select_joint_edge(tree, node, hidden_state, moves, rng),
+            (None, None) => self.tree_policy.select_edge(tree, node, edges, hidden_state, rng),
+        };
+        if current_player == descent.root_player {
+            descent.opponent_moved = false;
+        } else if opponent && pass_action != Some(tree.edge(selected_edge).label) {
+            descent.opponent_moved = true;
+        }
+        selected_edge
+    }
 
-                //if edge.is_dangling() {
-                //    return (hidden_state, edges)
-                //}
+    // The edges of the action in every tree, labelled with what the player of the tree sees of it
+    fn descend(
+        &self,
+        trees: &[SharedTree<P::Player, P::Action>],
+        nodes: &[NodeId],
+        hidden_state: &P::HiddenState,
+        action: &P::Action,
+    ) -> Vec<EdgeId> {
+        self.search_problem
+            .get_all_players()
+            .iter()
+            .zip(trees.iter())
+            .zip(nodes.iter())
+            .map(|((player, tree), node)| {
+                tree.get_or_add_edge(*node, &self.search_problem.get_visible_action(hidden_state, action, player))
+            })
+            .collect()
+    }
 
-                if edge.is_dangling() {
-                    let obs = self.search_problem.get_observation(&hidden_state, hidden_state.current_actor());
-                    edge.set_action_reward(obs.reward());
-                    edge.create_child(hidden_state.current_actor(), obs.legal_actions());
+    // Creates the children of the edges that are still dangling, with their edges and priors,
+    // and returns the nodes the edges lead to
+    fn expand_edges(
+        &self,
+        trees: &[SharedTree<P::Player, P::Action>],
+        edges: Vec<EdgeId>,
+        hidden_state: &P::HiddenState,
+        action_reward: f32,
+        rng: &mut dyn RngCore,
+    ) -> Vec<NodeId> {
+        let mut nodes = vec![];
+        // the actions of a new node under progressive widening or sampling, the same in all the trees
+        let mut shared = None;
+        for (tree, edge) in trees.iter().zip(edges) {
+            // only when the edge on the player to move's tree is dangling,
+            // do we need to terminate the trajectory
+            if tree.read().edge(edge).is_dangling() {
+                let mut tree = tree.write();
+                // another search might have expanded it in the meantime
+                if tree.edge(edge).is_dangling() {
+                    tree.set_action_reward(edge, action_reward);
+                    self.expand(&mut tree, edge, hidden_state, &mut shared, rng);
                 }
-                nodes.push(edge.get_target_node())
             }
+            nodes.push(tree.read().edge(edge).get_target_node())
+        }
+        nodes
+    }
 
-            if trajectory_terminal {
-                return (hidden_state, nodes)
+    // Creates the child of a dangling edge, with the outcomes of a chance node, the widened or sampled actions
+    // (drawn once for all the trees) or else all the legal actions
+    fn expand(
+        &self,
+        tree: &mut Tree<P::Player, P::Action>,
+        edge: EdgeId,
+        hidden_state: &P::HiddenState,
+        shared: &mut Option<(Vec<P::Action>, Vec<f32>)>,
+        rng: &mut dyn RngCore,
+    ) {
+        let obs = self.search_problem.get_observation(hidden_state, hidden_state.current_actor());
+        let outcomes = self.search_problem.chance_outcomes(hidden_state);
+        let widening = self.widening(outcomes.is_some(), hidden_state);
+        let sampled = outcomes.is_none() && self.samples_actions(hidden_state);
+        let (actions, priors) = match widening {
+            Some(widening) => shared
+                .get_or_insert_with(|| self.widened_actions(hidden_state, vec![], widening.edges(1), rng))
+                .clone(),
+            None if sampled => shared.get_or_insert_with(|| self.sample_actions(hidden_state, rng)).clone(),
+            None => (obs.legal_actions(), vec![]),
+        };
+        let child = tree.create_child(edge, hidden_state.current_actor(), actions);
+        match outcomes {
+            Some(outcomes) => tree.set_transitions(child, &outcomes),
+            None if widening.is_none() && !sampled => self.set_priors(tree, child, hidden_state),
+            None if !priors.is_empty() => tree.set_priors(child, &priors),
+            None => {}
+        }
+    }
+
+    // Decoupled selection: every player moving picks one of its actions with its own statistics
+    // at the node, and the edge of the resulting joint action is followed
+    fn select_joint_edge(
+        &self,
+        tree: &Tree<P::Player, P::Action>,
+        node: NodeId,
+        hidden_state: &P::HiddenState,
+        moves: SimultaneousMoves<P::Player, P::Action>,
+        rng: &mut dyn RngCore,
+    ) -> EdgeId {
+        let min_max_stats = tree.get_min_max_stats();
+        let mut stats = tree.get_statistics_lock(node);
+        let decoupled = stats.decoupled_or_default();
+        if decoupled.is_empty() {
+            decoupled.players = moves.iter().map(|(player, _)| self.player_index(*player)).collect();
+            decoupled.arms = moves.iter().map(|(_, actions)| vec![Default::default(); actions.len()]).collect();
+        }
+        let mut selected_arms = vec![];
+        let mut actions = vec![];
+        for ((_, player_actions), arms) in moves.iter().zip(decoupled.arms.iter_mut()) {
+            let (arm, probability) = self.multi_player.decoupled_policy.select(arms, |value| min_max_stats.normalize(value), rng);
+            selected_arms.push((arm, probability));
+            actions.push(player_actions[arm]);
+        }
+        let joint_action = self.search_problem.simultaneous().unwrap().joint_action(hidden_state, &actions);
+        let edge = tree.get_edge(node, &joint_action);
+        let position = tree.edges(node).iter().position(|e| *e == edge).unwrap();
+        decoupled.remember(position, selected_arms);
+        edge
+    }
+
+    // Adds the returns of the players to the arms they picked at a simultaneous move
+    fn update_decoupled(&self, tree: &Tree<P::Player, P::Action>, node: NodeId, edge: EdgeId, returns: &[(P::Player, f32)]) {
+        let min_max_stats = tree.get_min_max_stats();
+        let mut stats = tree.get_statistics_lock(node);
+        let decoupled = match stats.decoupled_mut() {
+            Some(decoupled) => decoupled,
+            None => return,
+        };
+        let selection = match decoupled.selection(tree.edge_position(edge)) {
+            Some(selection) => selection.to_vec(),
+            None => return,
+        };
+        for (ix, (arm, probability)) in selection.into_iter().enumerate() {
+            let value = index_for_player(returns, &self.players[decoupled.players[ix]]);
+            self.multi_player.decoupled_policy.update(&mut decoupled.arms[ix], arm, probability, value, min_max_stats.normalize(value));
+        }
+    }
+
+    // Adds the value of the edge into the node to the AMAF statistics of the edges of the parent
+    // whose actions the player to move there played from there on, then adds the action of the edge
+    // to the actions played later
+    fn update_amaf(
+        tree: &Tree<P::Player, P::Action>,
+        parent: NodeId,
+        node: NodeId,
+        value: f32,
+        later: &mut Vec<(P::Player, P::Action)>,
+    ) {
+        let player = tree.node(parent).label;
+        later.push((player, tree.edge(tree.get_incoming_edge(node).unwrap()).label));
+        if tree.node(parent).is_chance() {
+            return
+        }
+        let mut stats = tree.get_statistics_lock(parent);
+        for (position, edge) in tree.edges(parent).iter().enumerate() {
+            let action = tree.edge(*edge).label;
+            if later.iter().any(|(p, a)| *p == player && *a == action) {
+                stats.add_amaf_sample(position, value, 1);
             }
         }
-        (hidden_state, vec![])
     }
 
-    #[deprecated]
-    fn expand<'a>(
+    fn determinize(&self, hidden_state: P::HiddenState, rng: &mut dyn RngCore) -> P::HiddenState {
+        match &self.multi_player.determinizer {
+            Some(determinizer) =>
+                determinizer.determinize(&self.search_problem, &hidden_state, hidden_state.current_actor(), rng),
+            None => hidden_state,
+        }
+    }
+
+    // With determinizations, the actions legal in the sampled state might be new to the node.
+    // Adds the missing edges and returns the legal ones, none at chance nodes or without
+    // a determinizer (where all the edges are legal).
+    fn add_legal_edges(
         &self,
+        tree: &SharedTree<P::Player, P::Action>,
+        node: NodeId,
         hidden_state: &P::HiddenState,
-        edges: &Vec<&'a Edge<P::Player, P::Action>>
-    ) -> Vec<&'a Node<P::Player, P::Action>> {
-        let mut nodes = vec![];
-        for (edge, player) in edges.iter().zip(self.players.iter()) {
-            if edge.is_dangling() {
-                let observation = self.search_problem.get_observation(hidden_state, *player);
-                edge.create_child(hidden_state.current_actor(), observation.legal_actions());
-                nodes.push(edge.get_target_node());
+    ) -> Vec<EdgeId> {
+        if self.multi_player.determinizer.is_none() || tree.read().node(node).is_chance() {
+            return vec![]
+        }
+        self.search_problem
+            .get_observation(hidden_state, hidden_state.current_actor())
+            .legal_actions()
+            .iter()
+            .map(|action| tree.get_or_add_edge(node, action))
+            .collect()
+    }
+
+    fn widening(&self, chance: bool, hidden_state: &P::HiddenState) -> Option<Widening> {
+        let widening = if chance { self.expansion.outcome_widening } else { self.expansion.widening };
+        // terminal states have no actions, whatever the sampler proposes
+        widening.filter(|_| {
+            self.multi_player.determinizer.is_none() && !hidden_state.is_terminal()
+                && self.simultaneous_moves(hidden_state).is_none()
+        })
+    }
+
+    fn samples_actions(&self, hidden_state: &P::HiddenState) -> bool {
+        self.expansion.action_proposal.is_some() && self.widening(false, hidden_state).is_none() && !hidden_state.is_terminal()
+            && self.multi_player.determinizer.is_none() && self.simultaneous_moves(hidden_state).is_none()
+    }
+
+    // Sampled MuZero: the distinct actions drawn from the proposal, with the priors beta_hat / beta * pi
+    // normalized, where beta_hat is the share of the draws of the action, beta its probability under
+    // the proposal and pi its prior (beta without a prior provider).
+    // A proposal that draws nothing gives all the legal actions, so that the node doesn't look terminal.
+    fn sample_actions(&self, hidden_state: &P::HiddenState, rng: &mut dyn RngCore) -> (Vec<P::Action>, Vec<f32>) {
+        let samples = self.expansion.action_proposal.as_ref().unwrap().sample(&self.search_problem, hidden_state, rng);
+        if samples.is_empty() {
+            let actions = self.search_problem.get_observation(hidden_state, hidden_state.current_actor()).legal_actions();
+            let priors = match &self.expansion.prior_provider {
+                Some(prior_provider) if !actions.is_empty() =>
+                    prior_provider.priors(&self.search_problem, hidden_state, &actions),
+                _ => vec![1.0 / actions.len() as f32; actions.len()],
+            };
+            return (actions, priors)
+        }
+        let mut actions = vec![];
+        let mut draws = vec![];
+        let mut probabilities = vec![];
+        for (action, probability) in samples.iter() {
+            match actions.iter().position(|a| a == action) {
+                Some(ix) => draws[ix] += 1,
+                None => {
+                    actions.push(*action);
+                    draws.push(1);
+                    probabilities.push(*probability);
+                }
+            }
+        }
+        let priors = match &self.expansion.prior_provider {
+            Some(prior_provider) if !actions.is_empty() =>
+                prior_provider.priors(&self.search_problem, hidden_state, &actions),
+            _ => probabilities.clone(),
+        };
+        let corrected: Vec<f32> = draws
+            .iter()
+            .zip(probabilities.iter())
+            .zip(priors.iter())
+            .map(|((draws, beta), pi)| *draws as f32 / samples.len() as f32 / beta.max(f32::MIN_POSITIVE) * pi)
+            .collect();
+        let total: f32 = corrected.iter().sum();
+        let priors = if total > 0.0 {
+            corrected.iter().map(|prior| prior / total).collect()
+        } else {
+            vec![1.0 / actions.len() as f32; actions.len()]
+        };
+        (actions, priors)
+    }
+
+    // Adds the edges the node is due after its selection, returns how many edges it may use
+    fn widen_node(
+        &self,
+        tree: &SharedTree<P::Player, P::Action>,
+        node: NodeId,
+        hidden_state: &P::HiddenState,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        let (chance, visits) = {
+            let tree = tree.read();
+            let visits = tree.get_statistics_lock(node).select_count();
+            (tree.node(node).is_chance(), visits)
+        };
+        let widening = self.widening(chance, hidden_state)?;
+        let limit = widening.edges(visits);
+        // only try again when the limit grows, the node might have run out of actions
+        if limit > widening.edges(visits.saturating_sub(1)) && tree.read().edges(node).len() < limit {
+            let mut tree = tree.write();
+            self.widen(&mut tree, node, hidden_state, limit, rng);
+        }
+        Some(limit)
+    }
+
+    // Adds edges to the node until it has the given number or there are no more actions
+    fn widen(
+        &self,
+        tree: &mut Tree<P::Player, P::Action>,
+        node: NodeId,
+        hidden_state: &P::HiddenState,
+        limit: usize,
+        rng: &mut dyn RngCore,
+    ) {
+        let tried: Vec<P::Action> = tree.edges(node).iter().map(|e| tree.edge(*e).label).collect();
+        let count = tried.len();
+        let (actions, priors) = self.widened_actions(hidden_state, tried, limit, rng);
+        for action in &actions[count..] {
+            tree.add_edge(node, *action);
+        }
+        if !actions.is_empty() {
+            tree.set_priors(node, &priors);
+        }
+    }
+
+    // The actions of a node with the given ones after widening it to the limit, and the priors of all of them.
+    // Chance nodes get an outcome sampled with the probabilities, if it is new.
+    // Other nodes get the untried actions proposed by the sampler, or else the legal ones with the highest priors.
+    fn widened_actions(
+        &self,
+        hidden_state: &P::HiddenState,
+        mut actions: Vec<P::Action>,
+        limit: usize,
+        rng: &mut dyn RngCore,
+    ) -> (Vec<P::Action>, Vec<f32>) {
+        if let Some(outcomes) = self.search_problem.chance_outcomes(hidden_state) {
+            if actions.len() < limit {
+                let (outcome, _) = outcomes.choose_weighted(rng, |(_, p)| *p).unwrap();
+                if !actions.contains(outcome) {
+                    actions.push(*outcome);
+                }
+            }
+            let probabilities = actions
+                .iter()
+                .map(|action| outcomes.iter().find(|(outcome, _)| outcome == action).map_or(0.0, |(_, p)| *p))
+                .collect();
+            return (actions, probabilities)
+        }
+
+        let legal_actions = match &self.expansion.action_sampler {
+            Some(_) => vec![],
+            None => self.search_problem.get_observation(hidden_state, hidden_state.current_actor()).legal_actions(),
+        };
+        let legal_priors = match &self.expansion.prior_provider {
+            Some(prior_provider) if !legal_actions.is_empty() =>
+                prior_provider.priors(&self.search_problem, hidden_state, &legal_actions),
+            _ => vec![1.0; legal_actions.len()],
+        };
+        while actions.len() < limit {
+            let next = match &self.expansion.action_sampler {
+                Some(sampler) => sampler.sample(&self.search_problem, hidden_state, &actions, rng),
+                None => legal_actions
+                    .iter()
+                    .zip(legal_priors.iter())
+                    .filter(|(action, _)| !actions.contains(action))
+                    // the first of the best, as max_by returns the last
+                    .rev()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(action, _)| *action),
+            };
+            match next {
+                Some(action) => actions.push(action),
+                None => break,
+            }
+        }
+
+        let priors = match &self.expansion.prior_provider {
+            Some(_) if self.expansion.action_sampler.is_none() => actions
+                .iter()
+                .map(|action| legal_actions.iter().position(|a| a == action).map_or(0.0, |ix| legal_priors[ix]))
+                .collect(),
+            Some(prior_provider) => prior_provider.priors(&self.search_problem, hidden_state, &actions),
+            None => vec![1.0 / actions.len() as f32; actions.len()],
+        };
+        (actions, priors)
+    }
+
+    // The given number of edges with the highest priors, in their order in the node.
+    // For nodes created with all their edges, like the roots, except chance nodes.
+    fn best_edges(tree: &Tree<P::Player, P::Action>, edges: &[EdgeId], limit: usize) -> Vec<EdgeId> {
+        let mut by_prior = edges.to_vec();
+        by_prior.sort_by(|a, b| tree.edge(*b).get_prior().total_cmp(&tree.edge(*a).get_prior()));
+        by_prior.truncate(limit);
+        edges.iter().copied().filter(|edge| by_prior.contains(edge)).collect()
+    }
+
+    // Counts the visit of the parent for the children of all the legal edges, once expanded
+    fn count_available(tree: &SharedTree<P::Player, P::Action>, legal_edges: &[EdgeId]) {
+        let tree = tree.read();
+        for edge in legal_edges {
+            let edge = tree.edge(*edge);
+            if !edge.is_dangling() {
+                tree.get_statistics_lock(edge.get_target_node()).increment_availability_count();
+            }
+        }
+    }
+
+    fn set_priors(&self, tree: &mut Tree<P::Player, P::Action>, node: NodeId, hidden_state: &P::HiddenState) {
+        if let Some(prior_provider) = &self.expansion.prior_provider {
+            let actions: Vec<P::Action> = tree.edges(node).iter().map(|e| tree.edge(*e).label).collect();
+            let priors = prior_provider.priors(&self.search_problem, hidden_state, &actions);
+            tree.set_priors(node, &priors);
+        }
+    }
+
+    // The roots are not expanded by select, so their priors are set before searching.
+    // With an action proposal, roots that have not been searched yet get sampled actions instead,
+    // otherwise the root of the player to move gets the priors of the leaf evaluator, if it returns them.
+    // The root noise is mixed into the priors of the player to move.
+    pub(crate) fn set_root_priors(
+        &self,
+        hidden_state: &P::HiddenState,
+        trees: &[SharedTree<P::Player, P::Action>],
+        rng: &mut dyn RngCore,
+    ) {
+        if self.search_problem.chance_outcomes(hidden_state).is_some() {
+            return
+        }
+        if self.samples_actions(hidden_state) {
+            let mut sampled = None;
+            for tree in trees {
+                let mut tree = tree.write();
+                if tree.node_count() == 1 {
+                    let (actions, priors) = sampled.get_or_insert_with(|| self.sample_actions(hidden_state, rng)).clone();
+                    let root = tree.root();
+                    *tree = Tree::new(tree.node(root).label, actions);
+                    if !priors.is_empty() {
+                        tree.set_priors(root, &priors);
+                    }
+                }
+            }
+        } else {
+            if self.expansion.prior_provider.is_some() {
+                for tree in trees {
+                    let mut tree = tree.write();
+                    let root = tree.root();
+                    self.set_priors(&mut tree, root, hidden_state);
+                }
+            }
+            if let Some(leaf_evaluation) = self.leaf_evaluation.as_ref().filter(|_| !hidden_state.is_terminal()) {
+                let mut tree = trees[self.player_index(hidden_state.current_actor())].write();
+                let root = tree.root();
+                let actions: Vec<P::Action> = tree.edges(root).iter().map(|e| tree.edge(*e).label).collect();
+                let evaluation = leaf_evaluation.evaluator.evaluate(&self.search_problem, hidden_state, &actions, rng);
+                if let Some(priors) = evaluation.priors {
+                    tree.set_priors(root, &priors);
+                }
+            }
+        }
+        if let Some(root_noise) = &self.expansion.root_noise {
+            let mut tree = trees[self.player_index(hidden_state.current_actor())].write();
+            let root = tree.root();
+            let noise = sample_dirichlet(rng, root_noise.alpha, tree.edges(root).len());
+            tree.add_root_noise(&noise, root_noise.fraction);
+        }
+    }
+
+    fn add_virtual_loss(trees: &[SharedTree<P::Player, P::Action>], nodes: &[NodeId], virtual_loss: f32) {
+        if virtual_loss != 0.0 {
+            for (tree, node) in trees.iter().zip(nodes.iter()) {
+                tree.read().get_statistics_lock(*node).add_virtual_loss(virtual_loss);
+            }
+        }
+    }
+
+    // Value of a leaf for all the players, the discounted return after reaching it
+    pub(crate) fn evaluate(&self, hidden_state: P::HiddenState, rng: &mut dyn RngCore) -> Vec<(P::Player, f32)> {
+        // this handles already created terminal nodes correctly
+        if hidden_state.is_terminal() {
+            // nothing more to receive, the reward of reaching it is on the edge
+            return self.players.iter().map(|player| (*player, 0.0)).collect()
+        }
+        let evaluation = self
+            .leaf_evaluation
+            .as_ref()
+            .map(|leaf_evaluation| leaf_evaluation.evaluator.evaluate(&self.search_problem, &hidden_state, &[], rng).values);
+        let rollout = if self.rolls_out() {
+            Some(Rollout {
+                values: self.simulator.simulate(&self.search_problem, hidden_state, self.horizon, self.discount, rng),
+                actions: vec![],
+            })
+        } else {
+            None
+        };
+        self.mix(evaluation, rollout).values
+    }
+
+    // Like evaluate, with the actions of the rollout when AMAF statistics are kept.
+    // The priors from the leaf evaluator are set on the node of the player to move, if it is new.
+    pub(crate) fn rollout(
+        &self,
+        hidden_state: P::HiddenState,
+        trees: &[SharedTree<P::Player, P::Action>],
+        nodes: &[NodeId],
+        rng: &mut dyn RngCore,
+    ) -> Rollout<P> {
+        if hidden_state.is_terminal() {
+            return Rollout {
+                values: self.evaluate(hidden_state, rng),
+                actions: vec![],
+            }
+        }
+        let evaluation = self.evaluate_node(&hidden_state, trees, nodes, rng);
+        let rollout = if self.rolls_out() { Some(self.simulate(hidden_state, rng)) } else { None };
+        self.mix(evaluation, rollout)
+    }
+
+    // The values of the leaf evaluator for a state that is not terminal, None without one.
+    // The priors it returns are set on the node of the player to move, if it is new
+    // and was expanded with all the legal actions.
+    pub(crate) fn evaluate_node(
+        &self,
+        hidden_state: &P::HiddenState,
+        trees: &[SharedTree<P::Player, P::Action>],
+        nodes: &[NodeId],
+        rng: &mut dyn RngCore,
+    ) -> Option<Vec<(P::Player, f32)>> {
+        let leaf_evaluation = self.leaf_evaluation.as_ref()?;
+        let current_index = self.player_index(hidden_state.current_actor());
+        let leaf = match self.search_problem.chance_outcomes(hidden_state) {
+            None if self.widening(false, hidden_state).is_none() && !self.samples_actions(hidden_state) =>
+                Some((&trees[current_index], nodes[current_index])),
+            _ => None,
+        };
+        let actions: Vec<P::Action> = match leaf {
+            Some((tree, node)) => {
+                let tree = tree.read();
+                tree.edges(node).iter().map(|e| tree.edge(*e).label).collect()
+            }
+            None => vec![],
+        };
+        let evaluation = leaf_evaluation.evaluator.evaluate(&self.search_problem, hidden_state, &actions, rng);
+        if let (Some((tree, node)), Some(priors)) = (leaf, evaluation.priors) {
+            let mut tree = tree.write();
+            if tree.get_statistics_lock(node).sample_count() == 0 && tree.edges(node).len() == priors.len() {
+                tree.set_priors(node, &priors);
+            }
+        }
+        Some(evaluation.values)
+    }
+
+    // Leaves get rollouts unless only the leaf evaluator counts
+    pub(crate) fn rolls_out(&self) -> bool {
+        self.leaf_evaluation.as_ref().is_none_or(|leaf_evaluation| leaf_evaluation.lambda != 0.0)
+    }
+
+    // A rollout of the simulator from a state that is not terminal,
+    // with its actions when AMAF statistics are kept
+    pub(crate) fn simulate(&self, hidden_state: P::HiddenState, rng: &mut dyn RngCore) -> Rollout<P> {
+        if self.statistics.amaf {
+            self.simulator.simulate_with_actions(&self.search_problem, hidden_state, self.horizon, self.discount, rng)
+        } else {
+            Rollout {
+                values: self.simulator.simulate(&self.search_problem, hidden_state, self.horizon, self.discount, rng),
+                actions: vec![],
+            }
+        }
+    }
+
+    // The values of the leaf evaluator mixed with the rollout by lambda, each is None when it isn't used
+    pub(crate) fn mix(&self, evaluation: Option<Vec<(P::Player, f32)>>, rollout: Option<Rollout<P>>) -> Rollout<P> {
+        match (evaluation, rollout, &self.leaf_evaluation) {
+            (Some(values), None, _) => Rollout { values, actions: vec![] },
+            (None, Some(rollout), _) => rollout,
+            (Some(evaluation), Some(rollout), Some(leaf_evaluation)) => {
+                let lambda = leaf_evaluation.lambda;
+                let values = rollout
+                    .values
+                    .iter()
+                    .map(|(player, value)| (*player, (1.0 - lambda) * index_for_player(&evaluation, player) + lambda * value))
+                    .collect();
+                Rollout { values, actions: rollout.actions }
+            }
+            _ => unreachable!("rollouts are only skipped with a leaf evaluator"),
+        }
+    }
+
+    // Backs up the discounted returns along the trajectory, starting with the values of the leaf.
+    // A node holds the return of the edge into it (its reward + discount * the return after it)
+    // for the player that chose the edge, which is what the tree policy of the parent compares.
+    // The roots hold the return from the root for the player to move.
+    // Outcomes of chance nodes hold the return for the player that decided before the chance node,
+    // so that the chance node can weigh them by their probabilities instead of their visits.
+    // Several rollouts from the leaf are backed up together: the nodes get the average of their returns,
+    // and the actions played in every rollout count for the AMAF statistics with the returns of that rollout.
+    pub(crate) fn propagate(
+        &self,
+        trees: &[SharedTree<P::Player, P::Action>],
+        nodes: Vec<NodeId>,
+        rewards: &[Vec<(P::Player, f32)>],
+        rollouts: &[Rollout<P>],
+        virtual_loss: f32,
+    ) {
+        for (tree, node) in trees.iter().zip(nodes) {
+            let tree = tree.read();
+            let mut backup = Backup {
+                root_player: tree.node(tree.root()).label,
+                samples: rollouts
+                    .iter()
+                    .map(|rollout| self.players.iter().map(|player| (*player, index_for_player(&rollout.values, player))).collect())
+                    .collect(),
+                later: rollouts
+                    .iter()
+                    .map(|rollout| if self.statistics.amaf { rollout.actions.to_vec() } else { vec![] })
+                    .collect(),
+            };
+            let mut step_rewards = rewards.iter().rev();
+            let mut current = Some(node);
+            // nodes are solved from the leaf up, until one is not
+            let mut solving = self.statistics.solver;
+            while let Some(node) = current {
+                let after = if solving { self.solve(&tree, node, backup.root_player) } else { None };
+                solving = after.is_some();
+                self.correct_samples(&tree, node, after.as_deref(), &mut backup);
+                current = tree.get_parent(node);
+                if current.is_some() {
+                    self.add_step_rewards(step_rewards.next().unwrap(), &mut backup);
+                }
+                self.backup_node(&tree, node, current, after, &mut backup, virtual_loss);
+            }
+        }
+    }
+
+    // Replaces the returns after a node by the exact ones once it is solved, or by the value of
+    // its outcomes weighed by their probabilities for the player deciding before a chance node
+    fn correct_samples(&self, tree: &Tree<P::Player, P::Action>, node: NodeId, after: Option<&[f32]>, backup: &mut Backup<P>) {
+        match after {
+            Some(after) => for returns in backup.samples.iter_mut() {
+                for ((_, value), exact) in returns.iter_mut().zip(after) {
+                    *value = *exact;
+                }
+            }
+            None => if tree.node(node).is_chance() {
+                if let Some(value) = tree.chance_value(node) {
+                    let decider = Self::decider(tree, node);
+                    for returns in backup.samples.iter_mut() {
+                        self.set_backup_value(returns, decider, backup.root_player, value);
+                    }
+                }
+            }
+        }
+    }
+
+    // Turns the returns after a node into the returns of the edge into it
+    fn add_step_rewards(&self, step_rewards: &[(P::Player, f32)], backup: &mut Backup<P>) {
+        for returns in backup.samples.iter_mut() {
+            for (player, value) in returns.iter_mut() {
+                *value = index_for_player(step_rewards, player) + self.discount * *value;
             }
         }
-        nodes
     }
 
-    fn propagate(&self, mut nodes: Vec<&Node<P::Player, P::Action>>, mut values: Vec<(P::Player, f32)>) {
-        while !nodes.is_empty() {
-            let mut next = vec![];
-            for node in nodes {
-                node.get_statistics_lock().add_sample(index_for_player(&values, &node.label), 1);
-                if let Some(e) = node.get_incoming_edge() {
-                    next.push(e.get_incoming_node());
+    // Adds the average of the returns to the statistics of the node, and to the statistics of its parent
+    // at simultaneous moves or for AMAF. Proves the node when its exact returns are known.
+    fn backup_node(
+        &self,
+        tree: &Tree<P::Player, P::Action>,
+        node: NodeId,
+        parent: Option<NodeId>,
+        after: Option<Vec<f32>>,
+        backup: &mut Backup<P>,
+        virtual_loss: f32,
+    ) {
+        let count = backup.samples.len() as f32;
+        let returns: Vec<(P::Player, f32)> = self.players
+            .iter()
+            .enumerate()
+            .map(|(ix, player)| (*player, backup.samples.iter().map(|returns| returns[ix].1).sum::<f32>() / count))
+            .collect();
+        let player = match parent {
+            Some(parent) => {
+                if let Some(edge) = tree.get_incoming_edge(node) {
+                    self.update_decoupled(tree, parent, edge, &returns);
+                }
+                Self::decider(tree, parent)
+            }
+            None => tree.node(node).label,
+        };
+        let value = self.backup_value(&returns, player, backup.root_player);
+        if let (true, Some(parent)) = (self.statistics.amaf, parent) {
+            for (returns, later) in backup.samples.iter().zip(backup.later.iter_mut()) {
+                Self::update_amaf(tree, parent, node, self.backup_value(returns, player, backup.root_player), later);
+            }
+        }
+        let mut stats = tree.get_statistics_lock(node);
+        if let Some(after) = after {
+            if stats.proven().is_none() {
+                let returns = returns.iter().map(|(_, value)| *value).collect();
+                stats.prove(Proven { after, returns, value });
+            }
+        }
+        stats.add_sample(value, 1);
+        if virtual_loss != 0.0 {
+            stats.remove_virtual_loss(virtual_loss);
+        }
+        tree.update_min_max_stats(stats.expected_sample());
+    }
+
+    // The exact returns of all the players after a node, if its subtree is solved: terminal nodes are,
+    // chance nodes once all their outcomes are, and other nodes once all their children are
+    // or one of them is a proven win for the player to move
+    fn solve(&self, tree: &Tree<P::Player, P::Action>, node: NodeId, root_player: P::Player) -> Option<Vec<f32>> {
+        if let Some(proven) = tree.get_statistics_lock(node).proven() {
+            return Some(proven.after.clone())
+        }
+        if tree.node(node).is_terminal() {
+            return Some(vec![0.0; self.players.len()])
+        }
+        // joint actions and determinizations can't be maximised over
+        if self.multi_player.determinizer.is_some() || tree.get_statistics_lock(node).decoupled().is_some() {
+            return None
+        }
+        let chance = tree.node(node).is_chance();
+        // the actions or outcomes not added yet are not known
+        let widening = if chance { self.expansion.outcome_widening } else { self.expansion.widening };
+        // nor are the actions the proposal did not draw
+        if widening.is_some() || (!chance && self.expansion.action_proposal.is_some()) {
+            return None
+        }
+
+        let mut children = vec![];
+        let mut unsolved = false;
+        for edge in tree.edges(node) {
+            let edge = tree.edge(*edge);
+            let returns = if edge.is_dangling() {
+                None
+            } else {
+                tree.get_statistics_lock(edge.get_target_node()).proven().map(|proven| proven.returns.clone())
+            };
+            match returns {
+                Some(returns) => children.push((edge.get_prior(), returns)),
+                // outcomes that can't happen don't need to be solved
+                None => unsolved |= !chance || edge.get_prior() > 0.0,
+            }
+        }
+
+        if chance {
+            if unsolved {
+                return None
+            }
+            let total: f32 = children.iter().map(|(probability, _)| probability).sum();
+            let mut after = vec![0.0; self.players.len()];
+            for (probability, returns) in children {
+                for (value, exact) in after.iter_mut().zip(returns) {
+                    *value += probability / total * exact;
                 }
             }
-            nodes = next;
+            return Some(after)
+        }
+
+        let player = tree.node(node).label;
+        let value = |returns: &[f32]| {
+            let returns: Vec<(P::Player, f32)> = self.players.iter().copied().zip(returns.iter().copied()).collect();
+            self.backup_value(&returns, player, root_player)
+        };
+        let best = children
+            .into_iter()
+            .map(|(_, returns)| returns)
+            .max_by(|a, b| value(a).total_cmp(&value(b)))?;
+        let win = self.search_problem.max_return(player).is_some_and(|max_return| value(&best) >= max_return);
+        if unsolved && !win {
+            None
+        } else {
+            Some(best)
+        }
+    }
+
+    // The value a node holds for the player that chose the edge into it
+    fn backup_value(&self, returns: &[(P::Player, f32)], player: P::Player, root_player: P::Player) -> f32 {
+        match self.multi_player.strategy {
+            MultiPlayerStrategy::MaxN => index_for_player(returns, &player),
+            MultiPlayerStrategy::Paranoid | MultiPlayerStrategy::BestReply => {
+                let value = index_for_player(returns, &root_player);
+                if player == root_player { value } else { -value }
+            }
         }
     }
 
-    fn player_index(&self, player: P::Player) -> usize {
+    // Inverse of backup_value, sets the returns so that the player gets the value
+    fn set_backup_value(&self, returns: &mut [(P::Player, f32)], player: P::Player, root_player: P::Player, value: f32) {
+        let (target, value) = match self.multi_player.strategy {
+            MultiPlayerStrategy::MaxN => (player, value),
+            MultiPlayerStrategy::Paranoid | MultiPlayerStrategy::BestReply =>
+                (root_player, if player == root_player { value } else { -value }),
+        };
+        for (p, v) in returns.iter_mut() {
+            if *p == target {
+                *v = value;
+            }
+        }
+    }
+
+    // The player whose choice a node reflects, the closest ancestor that is not decided by chance
+    fn decider(tree: &Tree<P::Player, P::Action>, node: NodeId) -> P::Player {
+        let mut current = node;
+        while tree.node(current).is_chance() {
+            match tree.get_parent(current) {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+        tree.node(current).label
+    }
+
+    // The actions of every player moving at the same time in the state, if several do
+    pub(crate) fn simultaneous_moves(&self, hidden_state: &P::HiddenState) -> Option<SimultaneousMoves<P::Player, P::Action>> {
+        self.search_problem.simultaneous().and_then(|simultaneous| simultaneous.moves(hidden_state))
+    }
+
+    pub(crate) fn player_index(&self, player: P::Player) -> usize {
         for (ix, p) in self.players.iter().enumerate() {
             if player == *p {
                 return ix
@@ -133,36 +1138,746 @@ impl<T, P, S> MctsConfig<T, P, S>
         }
         usize::MAX
     }
+
+    // A new generator from the seed if there is one, made once for a search or for a whole game
+    pub(crate) fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
 }
 
 
-pub(crate) fn once<T, P, S>(config: &MctsConfig<T, P, S>, hidden_state: P::HiddenState, nodes: Vec<&Node<P::Player, P::Action>>)
+pub(crate) fn once<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: P::HiddenState,
+    trees: &[SharedTree<P::Player, P::Action>],
+    rng: &mut dyn RngCore,
+)
     where
         P: SearchProblem,
-        T: TreePolicy<Node<P::Player, P::Action>, P::HiddenState, Edge<P::Player, P::Action>>,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState>,
         S: Simulator<P> {
-    let (new_state, nodes) = config.select(hidden_state, nodes);
-    // this handles already created terminal nodes correctly
-    let mut value = vec![];
-    if new_state.is_terminal() {
-        // if one is terminal, then all are terminal
-        value = reward_for_all_players(&config.search_problem, &new_state);
-    } else {
-        value = config.simulator.simulate(
-            &config.search_problem,
-            new_state,
-            config.horizon,
-            config.discount
-        );
-    }
-    config.propagate(nodes, value);
-}
-
-pub(crate) fn initialise<P: SearchProblem>(p: &P, state: &P::HiddenState) -> Vec<Node<P::Player, P::Action>> {
+    once_with_virtual_loss(config, hidden_state, trees, 0.0, rng)
+}
+
+// A single iteration that marks the nodes on its path with a virtual loss
+// until the result has been propagated, used when several iterations share the trees
+pub(crate) fn once_with_virtual_loss<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: P::HiddenState,
+    trees: &[SharedTree<P::Player, P::Action>],
+    virtual_loss: f32,
+    rng: &mut dyn RngCore,
+)
+    where
+        P: SearchProblem,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState>,
+        S: Simulator<P> {
+    let trajectory = config.select(hidden_state, trees, virtual_loss, rng);
+    finish(config, trajectory, trees, virtual_loss, rng)
+}
+
+// A single iteration that plays the given action at the root, for drivers that pick the root actions
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) fn once_with_root_action<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: P::HiddenState,
+    trees: &[SharedTree<P::Player, P::Action>],
+    action: P::Action,
+    rng: &mut dyn RngCore,
+)
+    where
+        P: SearchProblem,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState>,
+        S: Simulator<P> {
+    let trajectory = config.select_with_root_action(hidden_state, trees, Some(action), 0.0, rng);
+    finish(config, trajectory, trees, 0.0, rng)
+}
+
+// Evaluates where the trajectory stopped and propagates the result
+fn finish<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    trajectory: Trajectory<P>,
+    trees: &[SharedTree<P::Player, P::Action>],
+    virtual_loss: f32,
+    rng: &mut dyn RngCore,
+)
+    where
+        P: SearchProblem,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState>,
+        S: Simulator<P> {
+    let rollout = match trajectory.proven {
+        Some(values) => Rollout { values, actions: vec![] },
+        None => config.rollout(trajectory.hidden_state, trees, &trajectory.nodes, rng),
+    };
+    config.propagate(trees, trajectory.nodes, &trajectory.rewards, &[rollout], virtual_loss);
+}
+
+pub(crate) fn initialise<P: SearchProblem>(p: &P, state: &P::HiddenState) -> Vec<SharedTree<P::Player, P::Action>> {
     let mut result = vec![];
     for player in p.get_all_players() {
-        let obs = p.get_observation(state, player);
-        result.push(Node::new(state.current_actor(), obs.legal_actions(), null()));
+        result.push(SharedTree::new(new_tree(p, state, player)));
+    }
+    result
+}
+
+fn new_tree<P: SearchProblem>(p: &P, state: &P::HiddenState, player: P::Player) -> Tree<P::Player, P::Action> {
+    let obs = p.get_observation(state, player);
+    let mut tree = Tree::new(state.current_actor(), obs.legal_actions());
+    if let Some(outcomes) = p.chance_outcomes(state) {
+        let root = tree.root();
+        tree.set_transitions(root, &outcomes);
+    }
+    tree
+}
+
+// Moves the roots of the trees along an action that was played from the given state
+// (resulting in new_state), keeping the subtrees (and their statistics) below it.
+// This is also how the outcome chosen by an environment player is observed.
+pub(crate) fn advance<P: SearchProblem>(
+    p: &P,
+    trees: Vec<SharedTree<P::Player, P::Action>>,
+    state: &P::HiddenState,
+    action: &P::Action,
+    new_state: &P::HiddenState,
+) -> Vec<SharedTree<P::Player, P::Action>> {
+    let mut result = vec![];
+    for (player, tree) in p.get_all_players().into_iter().zip(trees) {
+        let tree = tree.into_inner();
+        let visible_action = p.get_visible_action(state, action, &player);
+        // the action might be new to the tree when it was searched with other determinizations
+        let edge = tree.find_edge(tree.root(), &visible_action).map(|edge| tree.edge(edge));
+        let new_tree = match edge {
+            Some(edge) if !edge.is_dangling() => tree.subtree(edge.get_target_node()),
+            _ => new_tree(p, new_state, player),
+        };
+        result.push(SharedTree::new(new_tree));
     }
     result
 }
+
+#[cfg(test)]
+mod test {
+    use rand::RngCore;
+    use crate::lib::search::{ActionProposal, ActionSampler, LeafEvaluator, PriorProvider};
+    use crate::lib::search::budget::Budget;
+    use crate::lib::search::evaluation::{HeuristicEvaluator, NetworkEvaluator, RolloutEvaluator};
+    use crate::lib::search::mcts::{Expansion, initialise, LeafEvaluation, MctsConfig, MultiPlayer, MultiPlayerStrategy, RootNoise, Statistics, Widening};
+    use crate::lib::search::parallel::{Parallelism, run};
+    use crate::lib::search::policy::{PuctPolicy, RavePolicy, UctPolicy};
+    use crate::lib::search::searcher::{search, Searcher, SearchResult};
+    use crate::lib::search::simultaneous::DecoupledPolicy;
+    use crate::lib::search::tree::{SharedTree, Tree};
+    use crate::lib::search_problem::{HiddenState, Observation, SearchProblem};
+    use crate::lib::test_games::{Cards, Choices, Coin, CornerPrior, Deal, Digits, Dilemma, Flip, Hand, Line, Nim, Picked, Picks, Position, Roll, Sticks, Table, Trio};
+    use crate::lib::tzf8::{Action, Player, TwoZeroFourEight};
+    use crate::lib::tzf8::test::{board1, config as tzf8_config};
+    use crate::lib::utils::RandomSimulator;
+
+    #[test]
+    fn discounted_returns() {
+        let line = Line { length: 3 };
+        let start = Position { position: 0, length: line.length };
+        let config = MctsConfig {
+            discount: 0.5,
+            ..MctsConfig::new(line, vec![0], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(5), &mut config.rng());
+
+        // every node holds the exact return of the edge into it, whether it came
+        // from the tree or from a simulation
+        let tree = trees[0].read();
+        let mut node = tree.root();
+        for expected in [1.75, 1.75, 1.5, 1.0] {
+            assert_eq!(tree.get_statistics_lock(node).expected_sample(), expected);
+            if let Some(edge) = tree.edges(node).first() {
+                assert_eq!(tree.edge(*edge).get_action_reward(), Some(1.0));
+                node = tree.edge(*edge).get_target_node();
+            }
+        }
+    }
+
+    #[test]
+    fn chance_nodes() {
+        let start = Flip { stage: 0, reward: 0.0 };
+        let config = MctsConfig::new(Coin, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{});
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(500), &mut config.rng());
+
+        let tree = trees[0].read();
+        let chance_node = tree.edge(tree.edges(tree.root())[0]).get_target_node();
+        assert!(tree.node(chance_node).is_chance());
+        assert_eq!(tree.chance_value(chance_node), Some(0.9));
+        assert!((tree.get_statistics_lock(chance_node).expected_sample() - 0.9).abs() < 0.05);
+
+        let lose = tree.get_edge(chance_node, &2);
+        assert_eq!(tree.edge(lose).get_prior(), 0.1);
+        // outcomes hold the value for the player that flipped the coin
+        assert_eq!(tree.get_statistics_lock(tree.edge(lose).get_target_node()).expected_sample(), 0.0);
+    }
+
+    fn trio(strategy: MultiPlayerStrategy) -> Vec<SharedTree<u8, u8>> {
+        let start = Table::start();
+        let config = MctsConfig {
+            multi_player: MultiPlayer { strategy, ..Default::default() },
+            ..MctsConfig::new(Trio, vec![0, 1, 2], UctPolicy::new(0.3, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(3000), &mut config.rng());
+        trees
+    }
+
+    fn most_visited(tree: &Tree<u8, u8>) -> u8 {
+        let root = tree.root();
+        let visits = |action| tree.get_statistics_lock(tree.edge(tree.get_edge(root, &action)).get_target_node()).sample_count();
+        if visits(1) > visits(2) { 1 } else { 2 }
+    }
+
+    #[test]
+    fn multi_player_strategies() {
+        // every player looks after itself: player 1 splits side 1 and player 2 attacks anyway
+        let trees = trio(MultiPlayerStrategy::MaxN);
+        assert_eq!(most_visited(&trees[0].read()), 1);
+
+        // the opponents gang up on player 0 on side 1, side 2 is safer
+        let trees = trio(MultiPlayerStrategy::Paranoid);
+        assert_eq!(most_visited(&trees[0].read()), 2);
+
+        // once player 1 has replied, player 2 can only pass
+        let trees = trio(MultiPlayerStrategy::BestReply);
+        let tree = trees[0].read();
+        let side = tree.edge(tree.get_edge(tree.root(), &1)).get_target_node();
+        let replied = tree.edge(tree.get_edge(side, &2)).get_target_node();
+        assert!(tree.edge(tree.get_edge(replied, &1)).is_dangling());
+        let passed = tree.edge(tree.get_edge(side, &0)).get_target_node();
+        assert!(!tree.edge(tree.get_edge(passed, &1)).is_dangling());
+    }
+
+    #[test]
+    fn determinizations() {
+        let start = Hand::deal(1);
+        let config = MctsConfig {
+            multi_player: MultiPlayer { determinizer: Some(Box::new(Deal)), ..Default::default() },
+            ..MctsConfig::new(Cards, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(200), &mut config.rng());
+
+        for tree in trees.iter() {
+            let tree = tree.read();
+            // expanded with the card of one determinization, the other card was added later
+            let dealt = tree.edge(tree.edges(tree.root())[0]).get_target_node();
+            assert_eq!(tree.edges(dealt).len(), 2);
+            for card in [1, 2] {
+                assert!(!tree.edge(tree.get_edge(dealt, &card)).is_dangling());
+            }
+        }
+
+        // a single card is available on every visit of player 1, except the one expanding the node
+        let tree = trees[1].read();
+        let dealt = tree.edge(tree.edges(tree.root())[0]).get_target_node();
+        let available: u32 = tree.edges(dealt)
+            .iter()
+            .map(|edge| tree.get_statistics_lock(tree.edge(*edge).get_target_node()).availability_count())
+            .sum();
+        assert_eq!(available, tree.get_statistics_lock(dealt).sample_count() - 1);
+        // and player 1 only ever played the card it held
+        let other = tree.get_statistics_lock(tree.edge(tree.get_edge(dealt, &2)).get_target_node());
+        assert_eq!(other.availability_count(), other.sample_count());
+    }
+
+    #[test]
+    fn simultaneous_moves() {
+        // decoupled UCT can settle on both players cooperating, the others minimise regret
+        for decoupled_policy in [
+            DecoupledPolicy::Exp3 { gamma: 0.1 },
+            DecoupledPolicy::RegretMatching { gamma: 0.1 },
+        ] {
+            let config = MctsConfig {
+                multi_player: MultiPlayer { decoupled_policy, ..Default::default() },
+                ..MctsConfig::new(Dilemma, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+            };
+            let result = search(&config, &Choices::start(), Budget::Iterations(2000));
+            assert_eq!(result.best_action, Some(1), "{:?}", decoupled_policy);
+            assert_eq!(result.actions.len(), 2);
+        }
+    }
+
+    #[test]
+    fn searcher_at_simultaneous_moves() {
+        let config = MctsConfig {
+            multi_player: MultiPlayer {
+                decoupled_policy: DecoupledPolicy::RegretMatching { gamma: 0.1 },
+                ..Default::default()
+            },
+            ..MctsConfig::new(Dilemma, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut searcher = Searcher::new(config, Choices::start());
+        let result = searcher.search(Budget::Iterations(2000));
+        // the result has the actions of player 0, the game goes on with the joint action
+        assert_eq!(result.best_action, Some(1));
+        let visits = {
+            let tree = searcher.trees()[0].read();
+            let visits = tree.get_statistics_lock(tree.root()).sample_count();
+            visits
+        };
+        searcher.advance_joint(&[1, 0]);
+        assert_eq!(searcher.hidden_state().joint_action, Some(4));
+        assert_eq!(searcher.hidden_state().seen[0].reward, 5.0);
+        // and keeps the subtree of the joint action
+        let tree = searcher.trees()[0].read();
+        let kept = tree.get_statistics_lock(tree.root()).sample_count();
+        assert!(kept > 0 && kept < visits);
+    }
+
+    #[test]
+    fn solver() {
+        let start = Sticks::new(5);
+        let config = MctsConfig {
+            statistics: Statistics { solver: true, ..Default::default() },
+            ..MctsConfig::new(Nim, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        // the search stops once the root is solved
+        assert!(run(&config, &start, &mut trees, Budget::Iterations(10000), &mut config.rng()) < 10000);
+        let result = SearchResult::from_tree(&trees[0].read());
+        assert_eq!(result.best_action, Some(2));
+        assert_eq!(result.get(&2).unwrap().proven, Some(1.0));
+
+        // the chance node of the coin is solved with the probabilities of the outcomes
+        let start = Flip { stage: 0, reward: 0.0 };
+        let config = MctsConfig {
+            statistics: Statistics { solver: true, ..Default::default() },
+            ..MctsConfig::new(Coin, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        assert!(run(&config, &start, &mut trees, Budget::Iterations(500), &mut config.rng()) < 500);
+        let tree = trees[0].read();
+        let proven = tree.get_statistics_lock(tree.root()).proven().cloned().unwrap();
+        assert!((proven.after[0] - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn early_termination() {
+        // taking 1 of 4 sticks wins, taking 2 loses
+        let start = Sticks::new(4);
+        let config = MctsConfig {
+            early_termination: true,
+            seed: Some(0),
+            ..MctsConfig::new(Nim, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        let iterations = run(&config, &start, &mut trees, Budget::Iterations(1000), &mut config.rng());
+        assert!(iterations < 1000);
+        assert_eq!(SearchResult::from_tree(&trees[0].read()).best_action, Some(1));
+    }
+
+    #[test]
+    fn amaf() {
+        let start = Picked::start();
+        // the rollouts of every thread count under leaf parallelism
+        for parallelism in [Parallelism::Sequential, Parallelism::Leaf { threads: 2 }] {
+            let config = MctsConfig {
+                statistics: Statistics { amaf: true, ..Default::default() },
+                parallelism,
+                seed: Some(0),
+                ..MctsConfig::new(Picks, vec![0], RavePolicy::new(1.0, 10.0, vec![]), RandomSimulator{})
+            };
+            let mut trees = initialise(&config.search_problem, &start);
+            run(&config, &start, &mut trees, Budget::Iterations(10), &mut config.rng());
+
+            let tree = trees[0].read();
+            let root = tree.root();
+            let stats = tree.get_statistics_lock(root);
+            let third = tree.edge(tree.get_edge(root, &3)).get_target_node();
+            // 3 is also played after the other actions, in the tree and in the rollouts, and always wins
+            let (count, value) = stats.amaf(2);
+            assert!(count > tree.get_statistics_lock(third).sample_count());
+            assert_eq!(value, 1.0);
+            assert!(stats.amaf(0).1 < 1.0);
+            drop(stats);
+            drop(tree);
+            let result = SearchResult::from_tree(&trees[0].read());
+            assert_eq!(result.best_action, Some(3));
+        }
+    }
+
+    // Higher digits are more likely
+    struct Ascending;
+
+    impl PriorProvider<Digits> for Ascending {
+        fn priors(&self, _: &Digits, _: &Roll, actions: &[u8]) -> Vec<f32> {
+            let total: f32 = actions.iter().map(|action| (action + 1) as f32).sum();
+            actions.iter().map(|action| (action + 1) as f32 / total).collect()
+        }
+    }
+
+    // Proposes the even digits in order
+    struct Evens;
+
+    impl ActionSampler<Digits> for Evens {
+        fn sample(&self, _: &Digits, _: &Roll, tried: &[u8], _: &mut dyn RngCore) -> Option<u8> {
+            (0..10).step_by(2).find(|digit| !tried.contains(digit))
+        }
+    }
+
+    #[test]
+    fn progressive_widening() {
+        let widening = Widening { coefficient: 1.0, exponent: 0.5 };
+        let start = Roll { stage: 0, reward: 0.0 };
+        let mut config = MctsConfig {
+            expansion: Expansion {
+                prior_provider: Some(Box::new(Ascending)),
+                widening: Some(widening),
+                outcome_widening: Some(widening),
+                ..Default::default()
+            },
+            ..MctsConfig::new(Digits, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(50), &mut config.rng());
+
+        let tree = trees[0].read();
+        // the root has all its edges, but only the 8 with the highest priors are searched
+        let root = tree.root();
+        for digit in [0, 1] {
+            assert!(tree.edge(tree.get_edge(root, &digit)).is_dangling());
+        }
+        let mut stack = vec![(root, 0)];
+        while let Some((node, stage)) = stack.pop() {
+            let edges = tree.edges(node);
+            if stage > 0 && stage < 3 {
+                assert!(edges.len() <= widening.edges(tree.get_statistics_lock(node).select_count()));
+            }
+            if stage == 2 {
+                // the actions with the highest priors are added first
+                let labels: Vec<u8> = edges.iter().map(|e| tree.edge(*e).label).collect();
+                assert_eq!(labels, (10 - edges.len() as u8..10).rev().collect::<Vec<u8>>());
+            }
+            for edge in edges {
+                if !tree.edge(*edge).is_dangling() {
+                    stack.push((tree.edge(*edge).get_target_node(), stage + 1));
+                }
+            }
+        }
+        drop(tree);
+
+        // the sampler proposes the actions, and runs out of them
+        config.expansion.prior_provider = None;
+        config.expansion.action_sampler = Some(Box::new(Evens));
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(500), &mut config.rng());
+        let tree = trees[0].read();
+        let mut stack = vec![(tree.root(), 0)];
+        let mut widest = 0;
+        while let Some((node, stage)) = stack.pop() {
+            if stage == 2 {
+                let labels: Vec<u8> = tree.edges(node).iter().map(|e| tree.edge(*e).label).collect();
+                assert_eq!(labels, (0..10).step_by(2).take(labels.len()).collect::<Vec<u8>>());
+                widest = widest.max(labels.len());
+            }
+            for edge in tree.edges(node) {
+                if !tree.edge(*edge).is_dangling() {
+                    stack.push((tree.edge(*edge).get_target_node(), stage + 1));
+                }
+            }
+        }
+        assert_eq!(widest, 5);
+
+        // a chance root has all its outcomes, and they are all sampled even while the limit is lower
+        config.expansion.outcome_widening = Some(Widening { coefficient: 1.0, exponent: 0.25 });
+        let start = Roll { stage: 1, reward: 0.0 };
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(1000), &mut config.rng());
+        let tree = trees[0].read();
+        let root = tree.root();
+        assert_eq!(tree.edges(root).len(), 10);
+        assert!(tree.edges(root).iter().all(|e| !tree.edge(*e).is_dangling()));
+    }
+
+    // Draws 9 twice, 3 and 5 once
+    struct Fixed;
+
+    impl ActionProposal<Digits> for Fixed {
+        fn sample(&self, _: &Digits, _: &Roll, _: &mut dyn RngCore) -> Vec<(u8, f32)> {
+            vec![(9, 0.5), (3, 0.25), (9, 0.5), (5, 0.25)]
+        }
+    }
+
+    #[test]
+    fn sampled_actions() {
+        let start = Roll { stage: 0, reward: 0.0 };
+        let mut config = MctsConfig {
+            expansion: Expansion { action_proposal: Some(Box::new(Fixed)), ..Default::default() },
+            ..MctsConfig::new(Digits, vec![0, 1], PuctPolicy::muzero(vec![]), RandomSimulator{})
+        };
+        // without a prior provider the proposal is the prior, and the corrected priors are the share of the draws
+        assert_eq!(config.sample_actions(&start, &mut config.rng()), (vec![9, 3, 5], vec![0.5, 0.25, 0.25]));
+        // otherwise the priors are corrected by how often the actions were drawn compared to the proposal
+        config.expansion.prior_provider = Some(Box::new(Ascending));
+        let (_, priors) = config.sample_actions(&start, &mut config.rng());
+        for (prior, expected) in priors.iter().zip([0.5, 0.2, 0.3]) {
+            assert!((prior - expected).abs() < 1e-6);
+        }
+
+        let mut trees = initialise(&config.search_problem, &start);
+        run(&config, &start, &mut trees, Budget::Iterations(100), &mut config.rng());
+        let tree = trees[0].read();
+        // the nodes of the player only have the sampled actions, the roll has all the outcomes
+        let mut stack = vec![(tree.root(), 0)];
+        while let Some((node, stage)) = stack.pop() {
+            let labels: Vec<u8> = tree.edges(node).iter().map(|e| tree.edge(*e).label).collect();
+            match stage {
+                1 => assert_eq!(labels.len(), 10),
+                3 => assert!(labels.is_empty()),
+                _ => assert_eq!(labels, vec![9, 3, 5]),
+            }
+            for edge in tree.edges(node) {
+                if !tree.edge(*edge).is_dangling() {
+                    stack.push((tree.edge(*edge).get_target_node(), stage + 1));
+                }
+            }
+        }
+        drop(tree);
+        assert_eq!(SearchResult::from_tree(&trees[0].read()).best_action, Some(9));
+    }
+
+    // Draws the lowest digits, which are the worst
+    struct Low;
+
+    impl ActionProposal<Digits> for Low {
+        fn sample(&self, _: &Digits, _: &Roll, _: &mut dyn RngCore) -> Vec<(u8, f32)> {
+            vec![(0, 0.5), (1, 0.5)]
+        }
+    }
+
+    // Draws nothing
+    struct Empty;
+
+    impl ActionProposal<Digits> for Empty {
+        fn sample(&self, _: &Digits, _: &Roll, _: &mut dyn RngCore) -> Vec<(u8, f32)> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn solver_with_sampled_actions() {
+        let start = Roll { stage: 0, reward: 0.0 };
+        // the actions that were not drawn are unknown, so nothing above the leaves is proven
+        // and the search uses its whole budget
+        let config = MctsConfig {
+            statistics: Statistics { solver: true, ..Default::default() },
+            expansion: Expansion { action_proposal: Some(Box::new(Low)), ..Default::default() },
+            ..MctsConfig::new(Digits, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        assert_eq!(run(&config, &start, &mut trees, Budget::Iterations(1000), &mut config.rng()), 1000);
+        let tree = trees[0].read();
+        assert!(tree.get_statistics_lock(tree.root()).proven().is_none());
+        drop(tree);
+
+        // nodes the proposal draws nothing for get the legal actions instead of looking terminal
+        let config = MctsConfig {
+            statistics: Statistics { solver: true, ..Default::default() },
+            expansion: Expansion { action_proposal: Some(Box::new(Empty)), ..Default::default() },
+            ..MctsConfig::new(Digits, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+        };
+        let mut trees = initialise(&config.search_problem, &start);
+        assert_eq!(run(&config, &start, &mut trees, Budget::Iterations(100), &mut config.rng()), 100);
+        let tree = trees[0].read();
+        assert_eq!(tree.edges(tree.root()).len(), 10);
+        assert!(tree.get_statistics_lock(tree.root()).proven().is_none());
+    }
+
+    #[test]
+    fn root_noise() {
+        let start = Roll { stage: 0, reward: 0.0 };
+        let config = |seed| MctsConfig {
+            expansion: Expansion {
+                prior_provider: Some(Box::new(Ascending)),
+                root_noise: Some(RootNoise::new(0.3, 0.25)),
+                ..Default::default()
+            },
+            seed: Some(seed),
+            ..MctsConfig::new(Digits, vec![0, 1], PuctPolicy::muzero(vec![]), RandomSimulator{})
+        };
+        let root_priors = |config: &MctsConfig<_, Digits, _>| {
+            let trees = initialise(&config.search_problem, &start);
+            config.set_root_priors(&start, &trees, &mut config.rng());
+            let tree = trees[0].read();
+            tree.edges(tree.root()).iter().map(|e| tree.edge(*e).get_prior()).collect::<Vec<f32>>()
+        };
+
+        let priors = root_priors(&config(1));
+        assert!((priors.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        // at least three quarters of every prior are kept
+        for (digit, prior) in priors.iter().enumerate() {
+            let prior_without_noise = (digit + 1) as f32 / 55.0;
+            assert!(*prior >= 0.75 * prior_without_noise - 1e-6);
+            assert!(*prior <= 0.75 * prior_without_noise + 0.25 + 1e-6);
+        }
+        assert_ne!(priors, root_priors(&config(2)));
+        assert_eq!(priors, root_priors(&config(1)));
+
+        // searching the same root again mixes new noise into the uniform priors, it doesn't compound
+        let mut config = config(1);
+        config.expansion.prior_provider = None;
+        let trees = initialise(&config.search_problem, &start);
+        let mut rng = config.rng();
+        for _ in 0..20 {
+            config.set_root_priors(&start, &trees, &mut rng);
+            let tree = trees[0].read();
+            for edge in tree.edges(tree.root()) {
+                assert!(tree.edge(*edge).get_prior() >= 0.75 * 0.1 - 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn reproducible() {
+        let start = Roll { stage: 0, reward: 0.0 };
+        let config = |seed| MctsConfig {
+            expansion: Expansion {
+                outcome_widening: Some(Widening { coefficient: 1.0, exponent: 0.5 }),
+                root_noise: Some(RootNoise::new(0.3, 0.25)),
+                ..Default::default()
+            },
+            seed: Some(seed),
+            ..MctsConfig::new(Digits, vec![0, 1], PuctPolicy::muzero(vec![]), RandomSimulator{})
+        };
+        // every node of the tree of player 0 with the priors of its edges, depth first
+        let dump = |config: &MctsConfig<_, Digits, _>| {
+            let mut trees = initialise(&config.search_problem, &start);
+            run(config, &start, &mut trees, Budget::Iterations(300), &mut config.rng());
+            let tree = trees[0].read();
+            let mut result = vec![];
+            let mut stack = vec![tree.root()];
+            while let Some(node) = stack.pop() {
+                let stats = tree.get_statistics_lock(node);
+                let priors: Vec<f32> = tree.edges(node).iter().map(|e| tree.edge(*e).get_prior()).collect();
+                result.push((tree.node(node).label, stats.sample_count(), stats.expected_sample(), priors));
+                for edge in tree.edges(node) {
+                    if !tree.edge(*edge).is_dangling() {
+                        stack.push(tree.edge(*edge).get_target_node());
+                    }
+                }
+            }
+            result
+        };
+
+        // the same config searches the same trees every time
+        let seven = config(7);
+        assert_eq!(dump(&seven), dump(&seven));
+        assert_eq!(dump(&seven), dump(&config(7)));
+        assert_ne!(dump(&seven), dump(&config(8)));
+    }
+
+    #[test]
+    fn leaf_evaluation() {
+        let line = Line { length: 3 };
+        let start = Position { position: 0, length: line.length };
+        // the first iteration evaluates the node after the first step, where the rollout returns 1.5
+        let first_child = |evaluator: Box<dyn LeafEvaluator<Line> + Send + Sync>, lambda| {
+            let config = MctsConfig {
+                discount: 0.5,
+                leaf_evaluation: Some(LeafEvaluation { evaluator, lambda }),
+                ..MctsConfig::new(Line { length: 3 }, vec![0], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+            };
+            let mut trees = initialise(&config.search_problem, &start);
+            run(&config, &start, &mut trees, Budget::Iterations(1), &mut config.rng());
+            let tree = trees[0].read();
+            let edge = tree.edges(tree.root())[0];
+            let value = tree.get_statistics_lock(tree.edge(edge).get_target_node()).expected_sample();
+            value
+        };
+        let heuristic = || Box::new(HeuristicEvaluator { heuristic: |_: &Line, _: &Position| vec![(0, 4.0)] });
+        assert_eq!(first_child(heuristic(), 0.0), 1.0 + 0.5 * 4.0);
+        assert_eq!(first_child(heuristic(), 0.5), 1.0 + 0.5 * (0.5 * 4.0 + 0.5 * 1.5));
+        assert_eq!(first_child(heuristic(), 1.0), 1.0 + 0.5 * 1.5);
+        // rollouts of a step only see the next reward
+        let short_rollouts = || Box::new(RolloutEvaluator { simulator: RandomSimulator{}, horizon: 1, discount: 0.5 });
+        assert_eq!(first_child(short_rollouts(), 0.0), 1.0 + 0.5 * 1.0);
+        assert_eq!(first_child(short_rollouts(), 0.5), 1.0 + 0.5 * (0.5 * 1.0 + 0.5 * 1.5));
+
+        // the priors of the network replace the uniform ones on the nodes it evaluates
+        let network = |_: &Digits, _: &Roll, actions: &[u8]| {
+            (vec![(0, 0.0), (1, 0.0)], actions.iter().map(|action| *action as f32).collect())
+        };
+        let start = Roll { stage: 0, reward: 0.0 };
+        // with leaf parallelism the network evaluates the leaf once, and its rollouts are mixed in
+        for (parallelism, lambda) in [(Parallelism::Sequential, 0.0), (Parallelism::Leaf { threads: 2 }, 0.5)] {
+            let config = MctsConfig {
+                parallelism,
+                leaf_evaluation: Some(LeafEvaluation {
+                    evaluator: Box::new(NetworkEvaluator { network }),
+                    lambda,
+                }),
+                ..MctsConfig::new(Digits, vec![0, 1], UctPolicy::new(1.0, vec![]), RandomSimulator{})
+            };
+            let mut trees = initialise(&config.search_problem, &start);
+            run(&config, &start, &mut trees, Budget::Iterations(100), &mut config.rng());
+            let tree = trees[0].read();
+            let mut checked = 0;
+            let mut stack = vec![(tree.root(), 0)];
+            while let Some((node, stage)) = stack.pop() {
+                // the root and the nodes where player 0 moves again
+                if stage == 0 || stage == 2 {
+                    let total: f32 = (0..10).map(|digit| (digit as f32).exp()).sum();
+                    for (digit, edge) in tree.edges(node).iter().enumerate() {
+                        assert!((tree.edge(*edge).get_prior() - (digit as f32).exp() / total).abs() < 1e-6);
+                    }
+                    checked += 1;
+                }
+                for edge in tree.edges(node) {
+                    if !tree.edge(*edge).is_dangling() {
+                        stack.push((tree.edge(*edge).get_target_node(), stage + 1));
+                    }
+                }
+            }
+            assert!(checked > 1);
+        }
+    }
+
+    #[test]
+    fn root_priors_from_the_provider() {
+        let b = board1();
+        let config = MctsConfig {
+            horizon: 20,
+            expansion: Expansion { prior_provider: Some(Box::new(CornerPrior)), ..Default::default() },
+            ..MctsConfig::new(
+                TwoZeroFourEight{},
+                vec![Player::Environment, Player::Agent],
+                PuctPolicy::muzero(vec![Player::Environment]),
+                RandomSimulator{},
+            )
+        };
+        let trees = initialise(&config.search_problem, &b);
+        config.set_root_priors(&b, &trees, &mut config.rng());
+        {
+            let tree = trees[1].read();
+            assert_eq!(tree.edge(tree.get_edge(tree.root(), &Action::Left)).get_prior(), 0.4);
+        }
+
+        let result = search(&config, &b, Budget::Iterations(100));
+        assert!(result.best_action.is_some());
+    }
+
+    #[test]
+    fn chance_root() {
+        let b = board1().apply(&Action::Left);
+        let p = TwoZeroFourEight{};
+        let outcomes = p.chance_outcomes(&b).unwrap();
+        assert_eq!(outcomes.len(), b.legal_actions().len());
+
+        let trees = initialise(&p, &b);
+        {
+            let tree = trees[1].read();
+            assert!(tree.node(tree.root()).is_chance());
+        }
+        let result = search(&tzf8_config(Parallelism::Sequential), &b, Budget::Iterations(50));
+        assert!(matches!(result.best_action, Some(Action::Place(_, _))));
+    }
+}
@@ -1,12 +1,81 @@
+use rand::RngCore;
+use crate::lib::search::tree::{EdgeId, NodeId, Tree};
+use crate::lib::search_problem::SearchProblem;
+
 pub(crate) mod tree;
+pub(crate) mod budget;
+pub(crate) mod evaluation;
+pub(crate) mod expectimax;
+pub(crate) mod gumbel;
 pub(crate) mod mcts;
+pub(crate) mod policy;
+pub(crate) mod parallel;
+pub(crate) mod searcher;
+pub(crate) mod simultaneous;
 
-enum EdgeLabel<A, B> {
+// An edge is either an action chosen by a player or a transition picked by chance
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) enum EdgeLabel<A, B> {
     Action(A),
     Transition(B),
 }
 
+// Picks one of the given outgoing edges of the node, which are the ones legal in the hidden state
+// (all the edges of the node, unless the legal actions depend on the determinization).
+// Any randomness comes from the given generator, so that seeded searches can be repeated.
+pub(crate) trait TreePolicy<L, A, H> {
+    fn select_edge(&self, tree: &Tree<L, A>, node: NodeId, edges: &[EdgeId], hidden_state: &H, rng: &mut dyn RngCore) -> EdgeId;
+}
 
-pub(crate) trait TreePolicy<N, H, E> {
-    fn select_edge<'a>(&self, node: &'a N, hidden_state: &H) -> &'a E;
-}
\ No newline at end of file
+// Prior probabilities of the given actions at a state, stored on the edges when a node is expanded
+pub(crate) trait PriorProvider<P: SearchProblem> {
+    fn priors(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action]) -> Vec<f32>;
+}
+
+// Samples a hidden state consistent with everything the player has observed in the given one,
+// for searching games of imperfect information (ISMCTS)
+pub(crate) trait Determinizer<P: SearchProblem> {
+    fn determinize(&self, problem: &P, hidden_state: &P::HiddenState, player: P::Player, rng: &mut dyn RngCore) -> P::HiddenState;
+}
+
+// Proposes an action to add to a node under progressive widening, one that has not been tried there yet,
+// or None when there are no more. Lets action spaces too large to list be searched.
+pub(crate) trait ActionSampler<P: SearchProblem> {
+    fn sample(&self, problem: &P, hidden_state: &P::HiddenState, tried: &[P::Action], rng: &mut dyn RngCore) -> Option<P::Action>;
+}
+
+// The proposal distribution of Sampled MuZero: draws the actions a node is expanded with
+// (repeats allowed, as many as the implementation decides to sample) and gives the probability of
+// every draw under the proposal. Lets the search skip listing the legal actions.
+pub(crate) trait ActionProposal<P: SearchProblem> {
+    fn sample(&self, problem: &P, hidden_state: &P::HiddenState, rng: &mut dyn RngCore) -> Vec<(P::Action, f32)>;
+}
+
+// Estimates the discounted return of all the players after a hidden state that is not terminal,
+// in place of a rollout or mixed with one, and optionally the priors of the given actions there
+// (one for every action), like the value and policy heads of a network.
+pub(crate) trait LeafEvaluator<P: SearchProblem> {
+    fn evaluate(&self, problem: &P, hidden_state: &P::HiddenState, actions: &[P::Action], rng: &mut dyn RngCore) -> Evaluation<P>;
+}
+
+pub(crate) struct Evaluation<P: SearchProblem> {
+    pub(crate) values: Vec<(P::Player, f32)>,
+    pub(crate) priors: Option<Vec<f32>>,
+}
+
+// Estimated return of all the players from a state, used at the depth limit of expectimax
+// or to evaluate the leaves of a tree search
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) trait Heuristic<P: SearchProblem> {
+    fn evaluate(&self, problem: &P, hidden_state: &P::HiddenState) -> Vec<(P::Player, f32)>;
+}
+
+impl<P, F> Heuristic<P> for F
+    where
+        P: SearchProblem,
+        F: Fn(&P, &P::HiddenState) -> Vec<(P::Player, f32)> {
+    fn evaluate(&self, problem: &P, hidden_state: &P::HiddenState) -> Vec<(P::Player, f32)> {
+        self(problem, hidden_state)
+    }
+}
@@ -0,0 +1,279 @@
+use std::sync::mpsc;
+use std::thread;
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use crate::lib::search::TreePolicy;
+use crate::lib::search::budget::{Budget, BudgetTracker};
+use crate::lib::search::mcts::{initialise, MctsConfig, once, once_with_virtual_loss};
+use crate::lib::search::tree::{SharedTree, Tree};
+use crate::lib::search_problem::{HiddenState, SearchProblem};
+use crate::lib::{Rollout, Simulator};
+
+// How the iterations of a search are spread over threads
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Parallelism {
+    Sequential,
+    // All the threads share the same trees
+    #[cfg_attr(not(test), allow(dead_code))]
+    Tree { threads: usize, virtual_loss: f32 },
+    // Every thread builds its own trees, the statistics are merged at the root
+    #[cfg_attr(not(test), allow(dead_code))]
+    Root { threads: usize },
+    // Every expanded leaf is simulated once per thread, and the average is propagated
+    #[cfg_attr(not(test), allow(dead_code))]
+    Leaf { threads: usize },
+}
+
+// Runs iterations on the trees until the budget is used up, with the parallelism from the config.
+// Returns the number of iterations that were run. The random numbers come from the given generator,
+// every thread gets its own generator split from it.
+pub(crate) fn run<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: &P::HiddenState,
+    trees: &mut [SharedTree<P::Player, P::Action>],
+    budget: Budget,
+    rng: &mut dyn RngCore,
+) -> u32
+    where
+        P: SearchProblem + Sync,
+        P::HiddenState: Clone + Send + Sync,
+        P::Player: Send + Sync,
+        P::Action: Send + Sync,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState> + Sync,
+        S: Simulator<P> + Sync {
+    // without threads nothing would be searched, or nothing averaged for the leaves
+    if let Parallelism::Tree { threads, .. } | Parallelism::Root { threads } | Parallelism::Leaf { threads } = config.parallelism {
+        assert!(threads > 0, "{:?} needs at least one thread", config.parallelism);
+    }
+    config.set_root_priors(hidden_state, trees, rng);
+    let tracker = BudgetTracker::new(budget, config.early_termination);
+    match config.parallelism {
+        Parallelism::Sequential => {
+            let root_tree = &trees[config.player_index(hidden_state.current_actor())];
+            while start_iteration(&tracker, root_tree) {
+                once(config, hidden_state.clone(), trees, rng);
+                tracker.finish_iteration();
+            }
+        }
+        Parallelism::Tree { threads, virtual_loss } =>
+            tree_parallel(config, hidden_state, trees, &tracker, threads, virtual_loss, rng),
+        Parallelism::Root { threads } =>
+            root_parallel(config, hidden_state, trees, &tracker, threads, rng),
+        Parallelism::Leaf { threads } =>
+            leaf_parallel(config, hidden_state, trees, &tracker, threads, rng),
+    }
+    tracker.finished_iterations()
+}
+
+// There is nothing left to search once the root is solved
+fn start_iteration<L, A: PartialEq>(tracker: &BudgetTracker, root_tree: &SharedTree<L, A>) -> bool {
+    let root_tree = root_tree.read();
+    !solved(&root_tree) && !tracker.decided(&root_tree) && tracker.start_iteration(root_tree.node_count())
+}
+
+fn solved<L, A: PartialEq>(tree: &Tree<L, A>) -> bool {
+    tree.get_statistics_lock(tree.root()).proven().is_some()
+}
+
+// Tree parallelisation: all the threads run iterations on the same trees,
+// virtual loss keeps them from all following the same path.
+pub(crate) fn tree_parallel<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: &P::HiddenState,
+    trees: &[SharedTree<P::Player, P::Action>],
+    tracker: &BudgetTracker,
+    threads: usize,
+    virtual_loss: f32,
+    rng: &mut dyn RngCore,
+)
+    where
+        P: SearchProblem + Sync,
+        P::HiddenState: Clone + Sync,
+        P::Player: Send + Sync,
+        P::Action: Send + Sync,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState> + Sync,
+        S: Simulator<P> + Sync {
+    let root_tree = &trees[config.player_index(hidden_state.current_actor())];
+    thread::scope(|scope| {
+        for mut rng in split(rng, threads) {
+            scope.spawn(move || {
+                while start_iteration(tracker, root_tree) {
+                    once_with_virtual_loss(config, hidden_state.clone(), trees, virtual_loss, &mut rng);
+                    tracker.finish_iteration();
+                }
+            });
+        }
+    });
+}
+
+// Root parallelisation: every thread searches its own trees from the same state,
+// only the statistics of the roots and their children are merged into the given trees.
+// A node budget applies to the trees of every thread, and there is no early termination
+// as no thread sees the merged statistics.
+pub(crate) fn root_parallel<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: &P::HiddenState,
+    trees: &mut [SharedTree<P::Player, P::Action>],
+    tracker: &BudgetTracker,
+    threads: usize,
+    rng: &mut dyn RngCore,
+)
+    where
+        P: SearchProblem + Sync,
+        P::HiddenState: Clone + Sync,
+        P::Player: Send + Sync,
+        P::Action: Send + Sync,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState> + Sync,
+        S: Simulator<P> + Sync {
+    let root_index = config.player_index(hidden_state.current_actor());
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = split(rng, threads)
+            .into_iter()
+            .map(|mut rng| {
+                scope.spawn(move || {
+                    let local_trees = initialise(&config.search_problem, hidden_state);
+                    config.set_root_priors(hidden_state, &local_trees, &mut rng);
+                    while !solved(&local_trees[root_index].read())
+                        && tracker.start_iteration(local_trees[root_index].read().node_count()) {
+                        once(config, hidden_state.clone(), &local_trees, &mut rng);
+                        tracker.finish_iteration();
+                    }
+                    local_trees
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+
+    for local_trees in results {
+        for (tree, local_tree) in trees.iter_mut().zip(local_trees) {
+            tree.write().merge_root(&local_tree.into_inner());
+        }
+    }
+}
+
+// Leaf parallelisation: a single trajectory at a time, but the leaf it reaches
+// is simulated on every thread and the values are averaged before propagating
+// (and mixed with the leaf evaluator, which evaluates it once).
+// The threads are started once for the whole search and wait for the leaves.
+pub(crate) fn leaf_parallel<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: &P::HiddenState,
+    trees: &[SharedTree<P::Player, P::Action>],
+    tracker: &BudgetTracker,
+    threads: usize,
+    rng: &mut dyn RngCore,
+)
+    where
+        P: SearchProblem + Sync,
+        P::HiddenState: Clone + Send + Sync,
+        P::Player: Send + Sync,
+        P::Action: Send + Sync,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState> + Sync,
+        S: Simulator<P> + Sync {
+    let root_tree = &trees[config.player_index(hidden_state.current_actor())];
+    let rolls_out = config.rolls_out();
+    thread::scope(|scope| {
+        // the workers simulate the leaves sent to them until their channels close at the end of the search
+        let (results, samples) = mpsc::channel();
+        let workers: Vec<mpsc::Sender<P::HiddenState>> = split(rng, if rolls_out { threads } else { 0 })
+            .into_iter()
+            .map(|mut rng| {
+                let (leaves, received) = mpsc::channel();
+                let results = results.clone();
+                scope.spawn(move || {
+                    for leaf in received {
+                        results.send(config.simulate(leaf, &mut rng)).unwrap();
+                    }
+                });
+                leaves
+            })
+            .collect();
+
+        while start_iteration(tracker, root_tree) {
+            let trajectory = config.select(hidden_state.clone(), trees, 0.0, rng);
+            let leaf = trajectory.hidden_state;
+            let rollouts: Vec<Rollout<P>> = if let Some(values) = trajectory.proven {
+                vec![Rollout { values, actions: vec![] }]
+            } else if leaf.is_terminal() {
+                vec![Rollout { values: config.evaluate(leaf, rng), actions: vec![] }]
+            } else {
+                // the leaf evaluator is deterministic enough to run once, only the rollouts are averaged
+                let evaluation = config.evaluate_node(&leaf, trees, &trajectory.nodes, rng);
+                if rolls_out {
+                    for worker in &workers {
+                        worker.send(leaf.clone()).unwrap();
+                    }
+                    samples.iter().take(threads).map(|rollout| config.mix(evaluation.clone(), Some(rollout))).collect()
+                } else {
+                    vec![config.mix(evaluation, None)]
+                }
+            };
+            config.propagate(trees, trajectory.nodes, &trajectory.rewards, &rollouts, 0.0);
+            tracker.finish_iteration();
+        }
+    });
+}
+
+// Independent generators for the threads, drawn from the given one
+fn split(rng: &mut dyn RngCore, threads: usize) -> Vec<StdRng> {
+    (0..threads).map(|_| StdRng::from_rng(&mut *rng).unwrap()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::time::Duration;
+    use crate::lib::search::budget::Budget;
+    use crate::lib::search::mcts::initialise;
+    use crate::lib::search::parallel::{Parallelism, run};
+    use crate::lib::search::searcher::search;
+    use crate::lib::tzf8::test::{board1, config};
+
+    #[test]
+    fn every_parallelism_runs_the_budget() {
+        for parallelism in [
+            Parallelism::Tree { threads: 4, virtual_loss: 1.0 },
+            Parallelism::Root { threads: 3 },
+            Parallelism::Leaf { threads: 4 },
+        ] {
+            let b = board1();
+            let config = config(parallelism);
+            let mut trees = initialise(&config.search_problem, &b);
+            assert_eq!(run(&config, &b, &mut trees, Budget::Iterations(100), &mut config.rng()), 100);
+            for tree in trees.iter() {
+                let tree = tree.read();
+                assert_eq!(tree.get_statistics_lock(tree.root()).sample_count(), 100, "{:?}", parallelism);
+            }
+        }
+    }
+
+    #[test]
+    fn time_and_node_budgets() {
+        let b = board1();
+        let config = config(Parallelism::Sequential);
+        let result = search(&config, &b, Budget::Time(Duration::from_millis(20)));
+        assert!(result.best_action.is_some());
+
+        let mut trees = initialise(&config.search_problem, &b);
+        run(&config, &b, &mut trees, Budget::Nodes(50), &mut config.rng());
+        assert_eq!(trees[1].read().node_count(), 50);
+    }
+
+    #[test]
+    fn no_threads() {
+        for parallelism in [
+            Parallelism::Tree { threads: 0, virtual_loss: 1.0 },
+            Parallelism::Root { threads: 0 },
+            Parallelism::Leaf { threads: 0 },
+        ] {
+            let b = board1();
+            let config = config(parallelism);
+            let mut trees = initialise(&config.search_problem, &b);
+            let searched = panic::catch_unwind(AssertUnwindSafe(|| {
+                run(&config, &b, &mut trees, Budget::Iterations(10), &mut config.rng())
+            }));
+            assert!(searched.is_err(), "{:?}", parallelism);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use rand::RngCore;
+use rand::seq::SliceRandom;
+use crate::lib::search::TreePolicy;
+use crate::lib::search::tree::{EdgeId, NodeId, Tree};
+
+// UCB1 applied to trees. The player to move picks the edge maximising
+// value + exploration_constant * sqrt(ln(parent visits) / edge visits),
+// with the values normalized by the bounds seen in the tree.
+// When the legal actions depend on the determinization, the parent visits of an edge
+// are the visits during which it was available (subset-armed UCB).
+// Chance nodes and chance players pick an edge with the priors as probabilities.
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct UctPolicy<L> {
+    pub(crate) exploration_constant: f32,
+    pub(crate) chance_players: Vec<L>,
+}
+
+impl<L> UctPolicy<L> {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn new(exploration_constant: f32, chance_players: Vec<L>) -> Self {
+        UctPolicy {
+            exploration_constant,
+            chance_players,
+        }
+    }
+}
+
+impl<L, A, H> TreePolicy<L, A, H> for UctPolicy<L> where L: PartialEq, A: PartialEq {
+    fn select_edge(&self, tree: &Tree<L, A>, node: NodeId, edges: &[EdgeId], _: &H, rng: &mut dyn RngCore) -> EdgeId {
+        if tree.node(node).is_chance() || self.chance_players.contains(&tree.node(node).label) {
+            return sample_transition(tree, edges, rng)
+        }
+
+        let mut visits = Vec::with_capacity(edges.len());
+        for edge_id in edges.iter() {
+            let edge = tree.edge(*edge_id);
+            if edge.is_dangling() {
+                return *edge_id
+            }
+            let stats = tree.get_statistics_lock(edge.get_target_node());
+            if stats.visit_count() == 0 {
+                return *edge_id
+            }
+            visits.push((stats.visit_count(), stats.expected_sample(), stats.availability_count()));
+        }
+
+        let parent_visits: u32 = visits.iter().map(|(count, _, _)| count).sum();
+        let min_max_stats = tree.get_min_max_stats();
+        let mut best_score = f32::MIN;
+        let mut best_edge = edges[0];
+        for (edge_id, (count, value, available)) in edges.iter().zip(visits) {
+            let parent_visits = if available > 0 { available } else { parent_visits };
+            let exploration = ((parent_visits as f32).ln() / count as f32).sqrt();
+            let score = min_max_stats.normalize(value) + self.exploration_constant * exploration;
+            if score > best_score {
+                best_score = score;
+                best_edge = *edge_id;
+            }
+        }
+        best_edge
+    }
+}
+
+// The pUCT rule from MuZero, exploration is guided by the priors on the edges:
+// normalized value + prior * sqrt(parent visits) / (1 + edge visits) * (pb_c_init + ln((parent visits + pb_c_base + 1) / pb_c_base)).
+// Chance nodes and chance players pick an edge with the priors as probabilities.
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct PuctPolicy<L> {
+    pub(crate) pb_c_base: f32,
+    pub(crate) pb_c_init: f32,
+    pub(crate) chance_players: Vec<L>,
+}
+
+impl<L> PuctPolicy<L> {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn new(pb_c_base: f32, pb_c_init: f32, chance_players: Vec<L>) -> Self {
+        PuctPolicy {
+            pb_c_base,
+            pb_c_init,
+            chance_players,
+        }
+    }
+
+    // The constants used in the MuZero paper
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn muzero(chance_players: Vec<L>) -> Self {
+        PuctPolicy::new(19652.0, 1.25, chance_players)
+    }
+}
+
+impl<L, A, H> TreePolicy<L, A, H> for PuctPolicy<L> where L: PartialEq, A: PartialEq {
+    fn select_edge(&self, tree: &Tree<L, A>, node: NodeId, edges: &[EdgeId], _: &H, rng: &mut dyn RngCore) -> EdgeId {
+        if tree.node(node).is_chance() || self.chance_players.contains(&tree.node(node).label) {
+            return sample_transition(tree, edges, rng)
+        }
+
+        let parent_visits = tree.get_statistics_lock(node).visit_count() as f32;
+        let pb_c = ((parent_visits + self.pb_c_base + 1.0) / self.pb_c_base).ln() + self.pb_c_init;
+        let min_max_stats = tree.get_min_max_stats();
+        let mut best_score = f32::MIN;
+        let mut best_edge = edges[0];
+        for edge_id in edges.iter() {
+            let edge = tree.edge(*edge_id);
+            let (count, value) = if edge.is_dangling() {
+                (0, 0.0)
+            } else {
+                let stats = tree.get_statistics_lock(edge.get_target_node());
+                (stats.visit_count(), min_max_stats.normalize(stats.expected_sample()))
+            };
+            let score = value + pb_c * edge.get_prior() * parent_visits.sqrt() / (1 + count) as f32;
+            if score > best_score {
+                best_score = score;
+                best_edge = *edge_id;
+            }
+        }
+        best_edge
+    }
+}
+
+// UCT with rapid action value estimation: the value of an edge is blended with its
+// all-moves-as-first value, which has many more samples early on, as
+// (1 - beta) * value + beta * AMAF value with beta = sqrt(equivalence / (3 * edge visits + equivalence)).
+// The equivalence parameter is the number of visits at which both get the same weight.
+// Unvisited edges are tried first, the ones with the best AMAF value first (no AMAF samples count as best).
+// Needs MctsConfig::amaf, it is UCT without the AMAF statistics.
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct RavePolicy<L> {
+    pub(crate) exploration_constant: f32,
+    pub(crate) equivalence: f32,
+    pub(crate) chance_players: Vec<L>,
+}
+
+impl<L> RavePolicy<L> {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn new(exploration_constant: f32, equivalence: f32, chance_players: Vec<L>) -> Self {
+        RavePolicy {
+            exploration_constant,
+            equivalence,
+            chance_players,
+        }
+    }
+}
+
+impl<L, A, H> TreePolicy<L, A, H> for RavePolicy<L> where L: PartialEq, A: PartialEq {
+    fn select_edge(&self, tree: &Tree<L, A>, node: NodeId, edges: &[EdgeId], _: &H, rng: &mut dyn RngCore) -> EdgeId {
+        if tree.node(node).is_chance() || self.chance_players.contains(&tree.node(node).label) {
+            return sample_transition(tree, edges, rng)
+        }
+
+        let min_max_stats = tree.get_min_max_stats();
+        let amaf: Vec<(u32, f32)> = {
+            let stats = tree.get_statistics_lock(node);
+            edges.iter().map(|edge| stats.amaf(tree.edge_position(*edge))).collect()
+        };
+        let amaf_value = |(count, value): (u32, f32)| if count > 0 { min_max_stats.normalize(value) } else { f32::MAX };
+
+        let mut visits = Vec::with_capacity(edges.len());
+        let mut best_unvisited = None;
+        for (edge_id, amaf) in edges.iter().zip(amaf.iter()) {
+            let edge = tree.edge(*edge_id);
+            let stats = if edge.is_dangling() { None } else { Some(tree.get_statistics_lock(edge.get_target_node())) };
+            match stats {
+                Some(stats) if stats.visit_count() > 0 =>
+                    visits.push((stats.visit_count(), stats.expected_sample(), stats.availability_count())),
+                _ => match best_unvisited {
+                    Some((_, best)) if best >= amaf_value(*amaf) => {}
+                    _ => best_unvisited = Some((*edge_id, amaf_value(*amaf))),
+                }
+            }
+        }
+        if let Some((edge, _)) = best_unvisited {
+            return edge
+        }
+
+        let parent_visits: u32 = visits.iter().map(|(count, _, _)| count).sum();
+        let mut best_score = f32::MIN;
+        let mut best_edge = edges[0];
+        for ((edge_id, (count, value, available)), (amaf_count, amaf_value)) in edges.iter().zip(visits).zip(amaf) {
+            let beta = if amaf_count > 0 {
+                (self.equivalence / (3.0 * count as f32 + self.equivalence)).sqrt()
+            } else {
+                0.0
+            };
+            let value = (1.0 - beta) * min_max_stats.normalize(value) + beta * min_max_stats.normalize(amaf_value);
+            let parent_visits = if available > 0 { available } else { parent_visits };
+            let exploration = ((parent_visits as f32).ln() / count as f32).sqrt();
+            let score = value + self.exploration_constant * exploration;
+            if score > best_score {
+                best_score = score;
+                best_edge = *edge_id;
+            }
+        }
+        best_edge
+    }
+}
+
+// Samples one of the edges with the priors (the probabilities of the outcomes at chance nodes)
+pub(crate) fn sample_transition<L, A: PartialEq>(tree: &Tree<L, A>, edges: &[EdgeId], rng: &mut dyn RngCore) -> EdgeId {
+    *edges
+        .choose_weighted(rng, |e| tree.edge(*e).get_prior())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::lib::search::TreePolicy;
+    use crate::lib::search::policy::{PuctPolicy, RavePolicy, UctPolicy};
+    use crate::lib::search::budget::Budget;
+    use crate::lib::search::mcts::MctsConfig;
+    use crate::lib::search::searcher::search;
+    use crate::lib::search::tree::Tree;
+    use crate::lib::tzf8::{Player, TwoZeroFourEight};
+    use crate::lib::tzf8::test::board1;
+    use crate::lib::utils::RandomSimulator;
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0)
+    }
+
+    fn tree() -> Tree<u8, u8> {
+        tree_with([(1, 1.0, 10), (2, 0.5, 1)])
+    }
+
+    fn tree_with(children: [(u8, f32, u32); 2]) -> Tree<u8, u8> {
+        let mut tree = Tree::new(0, vec![1, 2]);
+        for (action, value, count) in children {
+            let edge = tree.get_edge(tree.root(), &action);
+            let child = tree.create_child(edge, 1, vec![]);
+            tree.get_statistics_lock(child).add_sample(value, count);
+        }
+        tree
+    }
+
+    #[test]
+    fn unvisited_first() {
+        let tree = Tree::new(0, vec![1, 2]);
+        let policy = UctPolicy::new(1.0, vec![]);
+        assert_eq!(policy.select_edge(&tree, tree.root(), tree.edges(tree.root()), &(), &mut rng()), tree.get_edge(tree.root(), &1));
+    }
+
+    #[test]
+    fn exploration() {
+        let tree = tree();
+        let greedy = UctPolicy::new(0.0, vec![]);
+        assert_eq!(greedy.select_edge(&tree, tree.root(), tree.edges(tree.root()), &(), &mut rng()), tree.get_edge(tree.root(), &1));
+        let exploring = UctPolicy::new(2.0, vec![]);
+        assert_eq!(exploring.select_edge(&tree, tree.root(), tree.edges(tree.root()), &(), &mut rng()), tree.get_edge(tree.root(), &2));
+    }
+
+    #[test]
+    fn legal_edges() {
+        // the unvisited edge is not legal, so the policy has to pick between the others
+        let mut tree = tree();
+        let root = tree.root();
+        let illegal = tree.add_edge(root, 3);
+        let legal = [tree.get_edge(root, &1), tree.get_edge(root, &2)];
+        let policy = UctPolicy::new(0.0, vec![]);
+        assert_eq!(policy.select_edge(&tree, root, &legal, &(), &mut rng()), legal[0]);
+        assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), illegal);
+    }
+
+    #[test]
+    fn normalized() {
+        // values 1000 times as large as in exploration, but normalized into [0, 1]
+        let tree = tree_with([(1, 1000.0, 10), (2, 500.0, 1)]);
+        tree.update_min_max_stats(0.0);
+        tree.update_min_max_stats(1000.0);
+        let exploring = UctPolicy::new(2.0, vec![]);
+        assert_eq!(exploring.select_edge(&tree, tree.root(), tree.edges(tree.root()), &(), &mut rng()), tree.get_edge(tree.root(), &2));
+    }
+
+    #[test]
+    fn chance() {
+        let tree = tree();
+        let policy = UctPolicy::new(0.0, vec![0]);
+        let mut rng = rng();
+        let mut seen = vec![];
+        for _ in 0..100 {
+            let edge = policy.select_edge(&tree, tree.root(), tree.edges(tree.root()), &(), &mut rng);
+            if !seen.contains(&edge) {
+                seen.push(edge);
+            }
+        }
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn puct_priors() {
+        let mut tree = Tree::new(0, vec![1, 2]);
+        let root = tree.root();
+        tree.get_statistics_lock(root).add_sample(0.0, 1);
+        tree.set_priors(root, &[0.2, 0.8]);
+        let policy = PuctPolicy::muzero(vec![]);
+        assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), tree.get_edge(root, &2));
+
+        // enough visits on the second edge make the first one worth a try
+        let edge = tree.get_edge(root, &2);
+        let child = tree.create_child(edge, 1, vec![]);
+        tree.get_statistics_lock(child).add_sample(0.0, 10);
+        tree.get_statistics_lock(root).add_sample(0.0, 10);
+        assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), tree.get_edge(root, &1));
+    }
+
+    #[test]
+    fn puct_chance() {
+        let mut tree = Tree::new(0, vec![1, 2]);
+        let root = tree.root();
+        tree.set_priors(root, &[0.0, 1.0]);
+        let policy = PuctPolicy::muzero(vec![0]);
+        for _ in 0..20 {
+            assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), tree.get_edge(root, &2));
+        }
+    }
+
+    #[test]
+    fn rave() {
+        // the AMAF value decides while the edges have few visits of their own
+        let tree = tree();
+        let root = tree.root();
+        tree.get_statistics_lock(root).add_amaf_sample(1, 2.0, 100);
+        let policy = RavePolicy::new(0.0, 1000.0, vec![]);
+        assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), tree.get_edge(root, &2));
+        let policy = RavePolicy::new(0.0, 0.0, vec![]);
+        assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), tree.get_edge(root, &1));
+
+        // unvisited edges are ordered by their AMAF value, edges without one come first
+        let tree = Tree::new(0, vec![1, 2, 3]);
+        let root = tree.root();
+        tree.get_statistics_lock(root).add_amaf_sample(0, 0.0, 5);
+        tree.get_statistics_lock(root).add_amaf_sample(1, 1.0, 5);
+        assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), tree.get_edge(root, &3));
+        tree.get_statistics_lock(root).add_amaf_sample(2, 0.5, 5);
+        assert_eq!(policy.select_edge(&tree, root, tree.edges(root), &(), &mut rng()), tree.get_edge(root, &2));
+    }
+
+    #[test]
+    fn uct_explores_every_action() {
+        let config = MctsConfig {
+            horizon: 20,
+            ..MctsConfig::new(
+                TwoZeroFourEight{},
+                vec![Player::Environment, Player::Agent],
+                UctPolicy::new(100.0, vec![Player::Environment]),
+                RandomSimulator{},
+            )
+        };
+        let result = search(&config, &board1(), Budget::Iterations(100));
+        assert!(result.actions.iter().all(|a| a.visit_count > 0));
+    }
+}
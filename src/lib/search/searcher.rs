@@ -0,0 +1,388 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::distributions::{Distribution, WeightedIndex};
+use crate::lib::search::TreePolicy;
+use crate::lib::search::mcts::{advance, initialise, MctsConfig};
+use crate::lib::search::budget::Budget;
+use crate::lib::search::gumbel::{Gumbel, gumbel_search};
+use crate::lib::search::parallel::run;
+use crate::lib::search::tree::{SharedTree, Tree};
+use crate::lib::search_problem::{HiddenState, SearchProblem};
+use crate::lib::Simulator;
+
+// Statistics of one action at the root, from the point of view of the root player
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct ActionStatistics<A> {
+    pub(crate) action: A,
+    pub(crate) visit_count: u32,
+    // 0 if the action was never visited
+    pub(crate) value: f32,
+    // visit count normalized over all the actions
+    pub(crate) probability: f32,
+    // the exact value, once the action is solved
+    pub(crate) proven: Option<f32>,
+}
+
+// How the action to play is picked from a search result
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) enum ActionSelection {
+    // the best action of the search
+    Best,
+    // sampled with probabilities proportional to visit count^(1 / temperature),
+    // a temperature of 0 is the most visited action
+    Temperature(f32),
+    // the temperature of the first entry whose move number has not been reached yet,
+    // the best action after the last one, like the first 30 moves at temperature 1 in AlphaZero
+    Schedule(Vec<(u32, f32)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SearchResult<A> {
+    // None when the root has no legal actions
+    pub(crate) best_action: Option<A>,
+    pub(crate) actions: Vec<ActionStatistics<A>>,
+}
+
+impl<A: Copy + PartialEq> SearchResult<A> {
+    // Reads the result out of the tree of the player to move at the root
+    pub(crate) fn from_tree<L>(tree: &Tree<L, A>) -> Self {
+        let root = tree.root();
+        let mut actions = vec![];
+        for edge_id in tree.edges(root) {
+            let edge = tree.edge(*edge_id);
+            let (visit_count, value, proven) = if edge.is_dangling() {
+                (0, 0.0, None)
+            } else {
+                let stats = tree.get_statistics_lock(edge.get_target_node());
+                (stats.sample_count(), stats.expected_sample(), stats.proven().map(|proven| proven.value))
+            };
+            actions.push(ActionStatistics {
+                action: edge.label,
+                visit_count,
+                value,
+                probability: 0.0,
+                proven,
+            });
+        }
+
+        let total_visits: u32 = actions.iter().map(|a| a.visit_count).sum();
+        for action in actions.iter_mut() {
+            action.probability = if total_visits == 0 {
+                1.0 / tree.edges(root).len() as f32
+            } else {
+                action.visit_count as f32 / total_visits as f32
+            };
+        }
+
+        // the best proven action when the root is solved,
+        // otherwise the most visited action, ties broken by value
+        let best_action = if tree.get_statistics_lock(root).proven().is_some() {
+            actions
+                .iter()
+                .filter(|a| a.proven.is_some())
+                .max_by(|a, b| a.proven.unwrap().total_cmp(&b.proven.unwrap()))
+                .map(|a| a.action)
+        } else {
+            actions
+                .iter()
+                .max_by(|a, b| a.visit_count.cmp(&b.visit_count).then(a.value.total_cmp(&b.value)))
+                .map(|a| a.action)
+        };
+
+        SearchResult {
+            best_action,
+            actions,
+        }
+    }
+
+    // Reads the result of a simultaneous move at the root out of the statistics of one of the
+    // players moving (its position in the simultaneous moves), whose actions are given.
+    // The actions are those of the player, not joint actions, see Searcher::advance_joint.
+    pub(crate) fn from_decoupled<L>(tree: &Tree<L, A>, player: usize, player_actions: &[A]) -> Self {
+        let stats = tree.get_statistics_lock(tree.root());
+        let actions: Vec<ActionStatistics<A>> = match stats.decoupled() {
+            None => player_actions
+                .iter()
+                .map(|action| ActionStatistics {
+                    action: *action,
+                    visit_count: 0,
+                    value: 0.0,
+                    probability: 1.0 / player_actions.len() as f32,
+                    proven: None,
+                })
+                .collect(),
+            Some(decoupled) => player_actions
+                .iter()
+                .zip(decoupled.arms[player].iter())
+                .zip(decoupled.policy(player))
+                .map(|((action, arm), probability)| ActionStatistics {
+                    action: *action,
+                    visit_count: arm.visits,
+                    value: arm.value,
+                    probability,
+                    proven: None,
+                })
+                .collect(),
+        };
+
+        // the action played most often, ties broken by value
+        let best_action = actions
+            .iter()
+            .max_by(|a, b| a.probability.total_cmp(&b.probability).then(a.value.total_cmp(&b.value)))
+            .map(|a| a.action);
+
+        SearchResult {
+            best_action,
+            actions,
+        }
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn get(&self, action: &A) -> Option<&ActionStatistics<A>> {
+        self.actions.iter().find(|a| a.action == *action)
+    }
+
+    // The action to play at the given move number (counted from 0) of the game
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn select_action<R: Rng>(&self, selection: &ActionSelection, move_number: u32, rng: &mut R) -> Option<A> {
+        let temperature = match selection {
+            ActionSelection::Best => return self.best_action,
+            ActionSelection::Temperature(temperature) => *temperature,
+            ActionSelection::Schedule(schedule) => match schedule.iter().find(|(until, _)| move_number < *until) {
+                Some((_, temperature)) => *temperature,
+                None => return self.best_action,
+            },
+        };
+        let most_visits = self.actions.iter().map(|a| a.visit_count).max().unwrap_or(0);
+        if most_visits == 0 {
+            return self.best_action
+        }
+        if temperature == 0.0 {
+            return self.actions.iter().find(|a| a.visit_count == most_visits).map(|a| a.action)
+        }
+        // relative to the most visits, so that low temperatures don't overflow
+        let weights = self.actions.iter().map(|a| (a.visit_count as f32 / most_visits as f32).powf(1.0 / temperature));
+        let index = WeightedIndex::new(weights).unwrap().sample(rng);
+        Some(self.actions[index].action)
+    }
+}
+
+// Searches from the given state and reports the statistics of the player to move
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) fn search<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: &P::HiddenState,
+    budget: Budget,
+) -> SearchResult<P::Action>
+    where
+        P: SearchProblem + Sync,
+        P::HiddenState: Clone + Send + Sync,
+        P::Player: Send + Sync,
+        P::Action: Send + Sync,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState> + Sync,
+        S: Simulator<P> + Sync {
+    let mut trees = initialise(&config.search_problem, hidden_state);
+    run(config, hidden_state, &mut trees, budget, &mut config.rng());
+    result(config, hidden_state, &trees)
+}
+
+// The result of the player to move at the root, from its own tree
+pub(crate) fn result<T, P, S>(
+    config: &MctsConfig<T, P, S>,
+    hidden_state: &P::HiddenState,
+    trees: &[SharedTree<P::Player, P::Action>],
+) -> SearchResult<P::Action>
+    where
+        P: SearchProblem,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState>,
+        S: Simulator<P> {
+    let root_player = hidden_state.current_actor();
+    let tree = trees[config.player_index(root_player)].read();
+    let moves = config.simultaneous_moves(hidden_state);
+    match moves.as_ref().and_then(|moves| moves.iter().position(|(player, _)| *player == root_player)) {
+        Some(player) => SearchResult::from_decoupled(&tree, player, &moves.unwrap()[player].1),
+        None => SearchResult::from_tree(&tree),
+    }
+}
+
+// Searches along a game, keeping the trees (and what was learned in them)
+// from one move to the next. A seeded searcher draws from one generator for the whole game,
+// so every move gets new noise while the game can still be repeated.
+pub(crate) struct Searcher<T, P: SearchProblem, S> {
+    config: MctsConfig<T, P, S>,
+    hidden_state: P::HiddenState,
+    trees: Vec<SharedTree<P::Player, P::Action>>,
+    rng: StdRng,
+}
+
+impl<T, P, S> Searcher<T, P, S>
+    where
+        P: SearchProblem + Sync,
+        P::HiddenState: Clone + Send + Sync,
+        P::Player: Send + Sync,
+        P::Action: Send + Sync,
+        T: TreePolicy<P::Player, P::Action, P::HiddenState> + Sync,
+        S: Simulator<P> + Sync {
+
+    pub(crate) fn new(config: MctsConfig<T, P, S>, hidden_state: P::HiddenState) -> Self {
+        let trees = initialise(&config.search_problem, &hidden_state);
+        let rng = config.rng();
+        Searcher {
+            config,
+            hidden_state,
+            trees,
+            rng,
+        }
+    }
+
+    pub(crate) fn search(&mut self, budget: Budget) -> SearchResult<P::Action> {
+        run(&self.config, &self.hidden_state, &mut self.trees, budget, &mut self.rng);
+        result(&self.config, &self.hidden_state, &self.trees)
+    }
+
+    // Searches with Gumbel root selection, the probabilities of the result are the improved policy
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn gumbel_search(&mut self, gumbel: &Gumbel, simulations: u32) -> SearchResult<P::Action> {
+        gumbel_search(&self.config, &self.hidden_state, &mut self.trees, gumbel, simulations, &mut self.rng)
+    }
+
+    // Plays an action (or observes the outcome of a chance event) in the searched state
+    pub(crate) fn advance(&mut self, action: &P::Action) {
+        let trees = std::mem::take(&mut self.trees);
+        let hidden_state = self.hidden_state.apply(action);
+        self.trees = advance(&self.config.search_problem, trees, &self.hidden_state, action, &hidden_state);
+        self.hidden_state = hidden_state;
+    }
+
+    // Plays the actions of all the players at a simultaneous move, in the order of the moves
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn advance_joint(&mut self, actions: &[P::Action]) {
+        let simultaneous = self.config.search_problem.simultaneous().expect("Only simultaneous moves have joint actions");
+        let joint_action = simultaneous.joint_action(&self.hidden_state, actions);
+        self.advance(&joint_action);
+    }
+
+    pub(crate) fn hidden_state(&self) -> &P::HiddenState {
+        &self.hidden_state
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn trees(&self) -> &[SharedTree<P::Player, P::Action>] {
+        &self.trees
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::lib::search::budget::Budget;
+    use crate::lib::search::mcts::{Expansion, MctsConfig, RootNoise};
+    use crate::lib::search::parallel::Parallelism;
+    use crate::lib::search::searcher::{search, ActionSelection, ActionStatistics, Searcher, SearchResult};
+    use crate::lib::search_problem::{HiddenState, Observation};
+    use crate::lib::tzf8::{ALL_ACTIONS, Player};
+    use crate::lib::tzf8::test::{board1, config};
+
+    fn result() -> SearchResult<u8> {
+        let actions = [(1, 0), (2, 10), (3, 30)]
+            .into_iter()
+            .map(|(action, visit_count)| ActionStatistics {
+                action,
+                visit_count,
+                value: 0.0,
+                probability: visit_count as f32 / 40.0,
+                proven: None,
+            })
+            .collect();
+        SearchResult {
+            best_action: Some(3),
+            actions,
+        }
+    }
+
+    #[test]
+    fn action_selection() {
+        let result = result();
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(result.select_action(&ActionSelection::Best, 0, &mut rng), Some(3));
+        assert_eq!(result.select_action(&ActionSelection::Temperature(0.0), 0, &mut rng), Some(3));
+
+        // unvisited actions are never played, the others in proportion to their visits
+        let draws: Vec<u8> = (0..1000)
+            .map(|_| result.select_action(&ActionSelection::Temperature(1.0), 0, &mut rng).unwrap())
+            .collect();
+        assert!(!draws.contains(&1));
+        let twos = draws.iter().filter(|action| **action == 2).count();
+        assert!(twos > 150 && twos < 350);
+
+        // the same seed plays the same actions
+        let mut other = StdRng::seed_from_u64(7);
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..10 {
+            let selection = ActionSelection::Temperature(1.0);
+            assert_eq!(result.select_action(&selection, 0, &mut rng), result.select_action(&selection, 0, &mut other));
+        }
+
+        // a high temperature early, the best action later
+        let schedule = ActionSelection::Schedule(vec![(2, 100.0)]);
+        assert!((0..100).any(|_| result.select_action(&schedule, 1, &mut rng) == Some(2)));
+        assert!((0..100).all(|_| result.select_action(&schedule, 2, &mut rng) == Some(3)));
+    }
+
+    #[test]
+    fn search_result() {
+        let result = search(&config(Parallelism::Sequential), &board1(), Budget::Iterations(100));
+        assert!(ALL_ACTIONS.contains(&result.best_action.unwrap()));
+        assert_eq!(result.actions.len(), 4);
+        let total: f32 = result.actions.iter().map(|a| a.probability).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        let best = result.get(&result.best_action.unwrap()).unwrap();
+        assert!(result.actions.iter().all(|a| a.visit_count <= best.visit_count));
+    }
+
+    #[test]
+    fn advance_keeps_the_subtree() {
+        let mut searcher = Searcher::new(config(Parallelism::Sequential), board1());
+        let result = searcher.search(Budget::Iterations(200));
+        let action = result.best_action.unwrap();
+        let visits = result.get(&action).unwrap().visit_count;
+
+        searcher.advance(&action);
+        assert_eq!(searcher.hidden_state().current_actor(), Player::Environment);
+        for tree in searcher.trees() {
+            let tree = tree.read();
+            assert_eq!(tree.get_statistics_lock(tree.root()).sample_count(), visits);
+        }
+
+        // the environment places a tile, possibly one that was never searched
+        let place = searcher.hidden_state().legal_actions()[0];
+        searcher.advance(&place);
+        assert_eq!(searcher.hidden_state().current_actor(), Player::Agent);
+        let result = searcher.search(Budget::Iterations(50));
+        assert!(result.best_action.is_some());
+    }
+
+    #[test]
+    fn new_noise_for_every_search() {
+        let game = || {
+            let config = MctsConfig {
+                expansion: Expansion { root_noise: Some(RootNoise::new(0.3, 0.25)), ..Default::default() },
+                seed: Some(3),
+                ..config(Parallelism::Sequential)
+            };
+            let mut searcher = Searcher::new(config, board1());
+            (0..2)
+                .map(|_| {
+                    searcher.search(Budget::Iterations(10));
+                    let tree = searcher.trees()[1].read();
+                    tree.edges(tree.root()).iter().map(|e| tree.edge(*e).get_prior()).collect::<Vec<f32>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let priors = game();
+        assert_ne!(priors[0], priors[1]);
+        assert_eq!(priors, game());
+    }
+}
@@ -0,0 +1,220 @@
+use rand::RngCore;
+use rand::distributions::{Distribution, WeightedIndex};
+
+// How every player picks its own action at a simultaneous move, independently of the others
+// (decoupled selection). Exp3 and regret matching mix in exploration with probability gamma,
+// and minimise regret so the strategies they play approach an equilibrium.
+// UCT is deterministic and can settle on a profile that is not one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum DecoupledPolicy {
+    Uct { exploration_constant: f32 },
+    #[cfg_attr(not(test), allow(dead_code))]
+    Exp3 { gamma: f32 },
+    #[cfg_attr(not(test), allow(dead_code))]
+    RegretMatching { gamma: f32 },
+}
+
+// Statistics of one action of one player at a simultaneous move
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Arm {
+    pub(crate) visits: u32,
+    // mean return of the player
+    pub(crate) value: f32,
+    // Exp3: sum of the estimated (normalized) rewards, regret matching: cumulative regret
+    weight: f32,
+    // regret matching: sum of the strategies, the average strategy converges to an equilibrium
+    strategy: f32,
+}
+
+// The arms of all the players moving at a node
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct DecoupledStatistics {
+    // indices of the moving players in the config, in the order of the arms
+    pub(crate) players: Vec<usize>,
+    pub(crate) arms: Vec<Vec<Arm>>,
+    // the arms that lead to every selected joint action with the probabilities they were picked with
+    // the last time, by the position of its edge in the node (edge ids change when the tree is copied)
+    selections: Vec<(usize, Vec<(usize, f32)>)>,
+}
+
+impl DecoupledPolicy {
+    // Picks an arm, with the probability it was picked with for the update
+    pub(crate) fn select(&self, arms: &mut [Arm], normalize: impl Fn(f32) -> f32, rng: &mut dyn RngCore) -> (usize, f32) {
+        match *self {
+            DecoupledPolicy::Uct { exploration_constant } => {
+                if let Some(unvisited) = arms.iter().position(|arm| arm.visits == 0) {
+                    return (unvisited, 1.0)
+                }
+                let log_visits = (arms.iter().map(|arm| arm.visits).sum::<u32>() as f32).ln();
+                let mut best_score = f32::MIN;
+                let mut best_arm = 0;
+                for (ix, arm) in arms.iter().enumerate() {
+                    let score = normalize(arm.value) + exploration_constant * (log_visits / arm.visits as f32).sqrt();
+                    if score > best_score {
+                        best_score = score;
+                        best_arm = ix;
+                    }
+                }
+                (best_arm, 1.0)
+            }
+            DecoupledPolicy::Exp3 { gamma } => {
+                let eta = gamma / arms.len() as f32;
+                let max_weight = arms.iter().map(|arm| arm.weight).fold(f32::MIN, f32::max);
+                let exponentials: Vec<f32> = arms.iter().map(|arm| (eta * (arm.weight - max_weight)).exp()).collect();
+                let total: f32 = exponentials.iter().sum();
+                let strategy: Vec<f32> = exponentials.iter().map(|e| e / total).collect();
+                sample(&strategy, gamma, rng)
+            }
+            DecoupledPolicy::RegretMatching { gamma } => {
+                let positive: f32 = arms.iter().map(|arm| arm.weight.max(0.0)).sum();
+                let strategy: Vec<f32> = arms
+                    .iter()
+                    .map(|arm| if positive > 0.0 { arm.weight.max(0.0) / positive } else { 1.0 / arms.len() as f32 })
+                    .collect();
+                for (arm, probability) in arms.iter_mut().zip(strategy.iter()) {
+                    arm.strategy += probability;
+                }
+                sample(&strategy, gamma, rng)
+            }
+        }
+    }
+
+    // Adds the return of the player that picked the arm with the given probability,
+    // normalized is the return scaled into [0, 1]
+    pub(crate) fn update(&self, arms: &mut [Arm], arm: usize, probability: f32, value: f32, normalized: f32) {
+        arms[arm].visits += 1;
+        arms[arm].value += (value - arms[arm].value) / arms[arm].visits as f32;
+        match *self {
+            DecoupledPolicy::Uct { .. } => {}
+            DecoupledPolicy::Exp3 { .. } => arms[arm].weight += normalized / probability,
+            DecoupledPolicy::RegretMatching { .. } => {
+                // importance sampled estimate: the return is only known for the picked arm
+                let estimate = normalized / probability;
+                for (ix, other) in arms.iter_mut().enumerate() {
+                    let reward = if ix == arm { estimate } else { 0.0 };
+                    other.weight += reward - normalized;
+                }
+            }
+        }
+    }
+}
+
+// Samples an arm from the strategy mixed with uniform exploration
+fn sample(strategy: &[f32], gamma: f32, rng: &mut dyn RngCore) -> (usize, f32) {
+    let uniform = 1.0 / strategy.len() as f32;
+    let probabilities: Vec<f32> = strategy.iter().map(|p| (1.0 - gamma) * p + gamma * uniform).collect();
+    let arm = WeightedIndex::new(&probabilities).unwrap().sample(rng);
+    (arm, probabilities[arm])
+}
+
+impl DecoupledStatistics {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.arms.is_empty()
+    }
+
+    pub(crate) fn remember(&mut self, edge: usize, arms: Vec<(usize, f32)>) {
+        match self.selections.iter_mut().find(|(e, _)| *e == edge) {
+            Some((_, selection)) => *selection = arms,
+            None => self.selections.push((edge, arms)),
+        }
+    }
+
+    pub(crate) fn selection(&self, edge: usize) -> Option<&[(usize, f32)]> {
+        self.selections.iter().find(|(e, _)| *e == edge).map(|(_, arms)| arms.as_slice())
+    }
+
+    // How often every arm of a player should be played: the average strategy for regret matching,
+    // the share of the visits otherwise
+    pub(crate) fn policy(&self, player: usize) -> Vec<f32> {
+        let arms = &self.arms[player];
+        let strategy: f32 = arms.iter().map(|arm| arm.strategy).sum();
+        let visits: u32 = arms.iter().map(|arm| arm.visits).sum();
+        arms.iter()
+            .map(|arm| {
+                if strategy > 0.0 {
+                    arm.strategy / strategy
+                } else if visits > 0 {
+                    arm.visits as f32 / visits as f32
+                } else {
+                    1.0 / arms.len() as f32
+                }
+            })
+            .collect()
+    }
+
+    // Adds the arms of the same node from another tree
+    pub(crate) fn merge(&mut self, other: &DecoupledStatistics) {
+        if self.is_empty() {
+            *self = other.clone();
+            return
+        }
+        for (arms, other_arms) in self.arms.iter_mut().zip(other.arms.iter()) {
+            for (arm, other) in arms.iter_mut().zip(other_arms) {
+                let visits = arm.visits + other.visits;
+                if visits > 0 {
+                    arm.value = (arm.value * arm.visits as f32 + other.value * other.visits as f32) / visits as f32;
+                }
+                arm.visits = visits;
+                arm.weight += other.weight;
+                arm.strategy += other.strategy;
+            }
+        }
+        for (edge, arms) in other.selections.iter() {
+            if self.selection(*edge).is_none() {
+                self.remember(*edge, arms.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::lib::search::simultaneous::{Arm, DecoupledPolicy};
+
+    #[test]
+    fn regret_matching() {
+        let policy = DecoupledPolicy::RegretMatching { gamma: 0.0 };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut arms = [Arm::default(); 2];
+        // no regrets yet, so both arms are equally likely
+        let (arm, probability) = policy.select(&mut arms, |v| v, &mut rng);
+        assert_eq!(probability, 0.5);
+
+        // the other arm would have been worth nothing, the picked one regrets less than the rest
+        policy.update(&mut arms, arm, probability, 1.0, 1.0);
+        assert_eq!(arms[arm].weight, 1.0);
+        assert_eq!(arms[1 - arm].weight, -1.0);
+        for _ in 0..10 {
+            assert_eq!(policy.select(&mut arms, |v| v, &mut rng), (arm, 1.0));
+        }
+    }
+
+    #[test]
+    fn uct() {
+        let policy = DecoupledPolicy::Uct { exploration_constant: 0.0 };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut arms = [Arm::default(); 2];
+        assert_eq!(policy.select(&mut arms, |v| v, &mut rng), (0, 1.0));
+        policy.update(&mut arms, 0, 1.0, 1.0, 1.0);
+        assert_eq!(policy.select(&mut arms, |v| v, &mut rng), (1, 1.0));
+        policy.update(&mut arms, 1, 1.0, 2.0, 1.0);
+        assert_eq!(policy.select(&mut arms, |v| v, &mut rng), (1, 1.0));
+    }
+
+    #[test]
+    fn exp3_weights_by_the_probability_of_the_selection() {
+        let policy = DecoupledPolicy::Exp3 { gamma: 0.5 };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut arms = [Arm::default(); 2];
+        let (arm, probability) = policy.select(&mut arms, |v| v, &mut rng);
+        assert_eq!(probability, 0.5);
+
+        // another selection in between (by another thread) doesn't change the estimate of this one
+        arms[1 - arm].weight = 10.0;
+        policy.select(&mut arms, |v| v, &mut rng);
+        policy.update(&mut arms, arm, probability, 1.0, 1.0);
+        assert_eq!(arms[arm].weight, 2.0);
+    }
+}
@@ -1,154 +1,749 @@
-use std::cell::{RefCell, RefMut};
-use std::ptr::null;
+use crate::lib::search::EdgeLabel;
+use crate::lib::search::simultaneous::DecoupledStatistics;
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-pub(crate) struct Node<L, A> {
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct NodeId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct EdgeId(usize);
+
+// Arena holding all the nodes and edges of a search tree.
+// Nodes and edges refer to each other through ids, so moving the tree
+// (or growing the underlying vectors) never invalidates a link.
+pub(crate) struct Tree<L, A> {
+    nodes: Vec<Node<L>>,
+    edges: Vec<Edge<A>>,
+    min_max_stats: Mutex<MinMaxStats>,
+    // The priors of the root before noise was mixed into them
+    noiseless_root_priors: Option<Vec<f32>>,
+}
+
+// A tree that can be searched from several threads.
+// Selection and statistics updates only take the read lock (statistics are
+// locked per node), expanding a node takes the write lock.
+pub(crate) struct SharedTree<L, A>(RwLock<Tree<L, A>>);
+
+pub(crate) struct Node<L> {
     pub(crate) label: L,
-    incoming_edge: *const Edge<L, A>,
-    outgoing_edges: Vec<Edge<L, A>>,
-    node_statistics: RefCell<NodeStatistics>,
+    // the outgoing edges are transitions picked by chance
+    chance: bool,
+    incoming_edge: Option<EdgeId>,
+    outgoing_edges: Vec<EdgeId>,
+    node_statistics: Mutex<NodeStatistics>,
 }
 
-pub(crate) struct Edge<L, A> {
-    parent_node: *const Node<L, A>,
+#[derive(Clone)]
+pub(crate) struct Edge<A> {
+    parent_node: NodeId,
 
-    target_node: Option<Node<L, A>>,
+    target_node: Option<NodeId>,
     pub(crate) label: A, // Action from the perspective of the root player
     // todo: remove option if 0.0 works
     action_reward: Option<f32>,
+    // Prior probability of choosing this edge, uniform unless set on expansion.
+    // For transitions, the probability of the outcome.
+    prior: f32,
 }
 
+#[derive(Clone)]
 pub(crate) struct NodeStatistics {
     select_count: u32,
     sample_count: u32,
     expected_reward: f32, // Expected reward for the player that played this edge
+    // Pending visits of concurrent searches, each counted as a loss
+    virtual_loss_count: u32,
+    virtual_loss: f32,
+    // Visits of the parent during which this edge was legal, for subset-armed bandits
+    // when the legal actions depend on the determinization
+    availability_count: u32,
+    // The actions of every player at a simultaneous move, boxed like the proven values
+    // so that the nodes of searches without them stay small
+    decoupled: Option<Box<DecoupledStatistics>>,
+    // Exact values once the subtree is solved
+    proven: Option<Box<Proven>>,
+    // All-moves-as-first statistics of the outgoing edges, by their position in the node:
+    // how often the action was played later by the player to move, and the mean return then
+    amaf: Vec<(u32, f32)>,
 }
 
-impl<L, A> Node<L, A> where A: PartialEq {
+// The exact values of a solved subtree, the returns are in the order of the players of the config
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Proven {
+    // returns of all the players after the node
+    pub(crate) after: Vec<f32>,
+    // returns of all the players for the edge into the node
+    pub(crate) returns: Vec<f32>,
+    // the return of the edge for the player that chose it, like expected_sample
+    pub(crate) value: f32,
+}
 
-    pub(crate) fn new(label: L, actions: Vec<A>, incoming_edge: *const Edge<L, A>) -> Self {
-        let mut result =
-            Node {
-                label,
-                incoming_edge,
-                outgoing_edges: vec![],
-                node_statistics: RefCell::new(NodeStatistics {
-                    select_count: 0,
-                    sample_count: 0,
-                    expected_reward: 0.0
-                })
-            };
+// Bounds of the values seen in a tree, used to normalize values into [0, 1]
+// when the scale of the rewards is not known in advance
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct MinMaxStats {
+    minimum: f32,
+    maximum: f32,
+}
+
+impl<L, A> Tree<L, A> where A: PartialEq {
+
+    pub(crate) fn new(label: L, actions: Vec<A>) -> Self {
+        let mut result = Tree {
+            nodes: vec![],
+            edges: vec![],
+            min_max_stats: Mutex::new(MinMaxStats::new()),
+            noiseless_root_priors: None,
+        };
+        result.add_node(label, actions, None);
+        result
+    }
+
+    fn add_node(&mut self, label: L, actions: Vec<A>, incoming_edge: Option<EdgeId>) -> NodeId {
+        let node_id = NodeId(self.nodes.len());
+        let mut outgoing_edges = vec![];
+        let prior = 1.0 / actions.len() as f32;
         for action in actions {
-            result.outgoing_edges.push(Edge {
-                parent_node: &result,
+            outgoing_edges.push(EdgeId(self.edges.len()));
+            self.edges.push(Edge {
+                parent_node: node_id,
                 target_node: None,
                 label: action,
-                action_reward: None
+                action_reward: None,
+                prior,
             })
         }
-        result
+        self.nodes.push(Node {
+            label,
+            chance: false,
+            incoming_edge,
+            outgoing_edges,
+            node_statistics: Mutex::new(NodeStatistics::new())
+        });
+        node_id
     }
 
+    pub(crate) fn root(&self) -> NodeId {
+        NodeId(0)
+    }
 
-    fn is_expanded(&self) -> bool {
-        true
+    pub(crate) fn node_count(&self) -> usize {
+        self.nodes.len()
     }
 
-    // we always create edges with all outgoing edges
-    // only edges can be dangling
-    pub(crate) fn is_terminal(&self) -> bool {
-        self.outgoing_edges.is_empty()
+    pub(crate) fn node(&self, node: NodeId) -> &Node<L> {
+        &self.nodes[node.0]
+    }
+
+    pub(crate) fn edge(&self, edge: EdgeId) -> &Edge<A> {
+        &self.edges[edge.0]
+    }
+
+    pub(crate) fn get_edge(&self, node: NodeId, label: &A) -> EdgeId {
+        match self.find_edge(node, label) {
+            Some(edge) => edge,
+            // edges for actions new to the node are added with add_edge
+            None => panic!("Edge not found")
+        }
+    }
+
+    pub(crate) fn find_edge(&self, node: NodeId, label: &A) -> Option<EdgeId> {
+        self.edges(node).iter().find(|edge| *label == self.edge(**edge).label).copied()
+    }
+
+    pub(crate) fn edges(&self, node: NodeId) -> &[EdgeId] {
+        &self.node(node).outgoing_edges
+    }
+
+    // Where the edge is among the outgoing edges of its node
+    pub(crate) fn edge_position(&self, edge: EdgeId) -> usize {
+        self.edges(self.edge(edge).get_incoming_node()).iter().position(|e| *e == edge).unwrap()
+    }
+
+    pub(crate) fn get_statistics_lock(&self, node: NodeId) -> MutexGuard<'_, NodeStatistics> {
+        self.node(node).node_statistics.lock().unwrap()
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn edge_label(&self, edge: EdgeId) -> EdgeLabel<A, (A, f32)> where A: Copy {
+        let e = self.edge(edge);
+        if self.node(e.parent_node).chance {
+            EdgeLabel::Transition((e.label, e.prior))
+        } else {
+            EdgeLabel::Action(e.label)
+        }
+    }
+
+    // Value of a chance node for the player that chose the edge into it: the average
+    // of the values of the expanded outcomes, weighted by their probabilities
+    pub(crate) fn chance_value(&self, node: NodeId) -> Option<f32> {
+        let mut total = 0.0;
+        let mut total_probability = 0.0;
+        for edge in self.edges(node) {
+            let edge = self.edge(*edge);
+            if edge.is_dangling() || edge.prior == 0.0 {
+                continue
+            }
+            let stats = self.get_statistics_lock(edge.get_target_node());
+            if stats.sample_count() > 0 {
+                total += edge.prior * stats.expected_sample();
+                total_probability += edge.prior;
+            }
+        }
+        if total_probability > 0.0 {
+            Some(total / total_probability)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn get_min_max_stats(&self) -> MinMaxStats {
+        *self.min_max_stats.lock().unwrap()
+    }
+
+    pub(crate) fn update_min_max_stats(&self, value: f32) {
+        self.min_max_stats.lock().unwrap().update(value)
     }
 
-    pub(crate) fn get_edge(&self, label: &A) -> &Edge<L, A> {
-        for edge in self.outgoing_edges.iter() {
-            if *label == edge.label {
-                return edge
+    pub(crate) fn get_incoming_edge(&self, node: NodeId) -> Option<EdgeId> {
+        self.node(node).incoming_edge
+    }
+
+    pub(crate) fn get_parent(&self, node: NodeId) -> Option<NodeId> {
+        self.get_incoming_edge(node).map(|e| self.edge(e).get_incoming_node())
+    }
+
+    pub(crate) fn create_child(&mut self, edge: EdgeId, label: L, actions: Vec<A>) -> NodeId {
+        assert!(self.edge(edge).is_dangling(), "Cannot rewrite.");
+        let target_node = self.add_node(label, actions, Some(edge));
+        self.edges[edge.0].target_node = Some(target_node);
+        target_node
+    }
+
+    // Adds an edge for an action that was not legal when the node was expanded,
+    // as happens when the search samples different determinizations.
+    // Transitions get a prior of 0 (the outcome probabilities are known on expansion),
+    // actions the uniform prior of the resulting edge count, and the priors of the other edges
+    // shrink so that the priors of the node still sum to 1.
+    pub(crate) fn add_edge(&mut self, node: NodeId, label: A) -> EdgeId {
+        let edge = EdgeId(self.edges.len());
+        self.nodes[node.0].outgoing_edges.push(edge);
+        let count = self.edges(node).len() as f32;
+        let prior = if self.node(node).is_chance() { 0.0 } else { 1.0 / count };
+        let shrink = 1.0 - prior;
+        for ix in 0..self.edges(node).len() - 1 {
+            let other = self.nodes[node.0].outgoing_edges[ix];
+            self.edges[other.0].prior *= shrink;
+        }
+        self.edges.push(Edge {
+            parent_node: node,
+            target_node: None,
+            label,
+            action_reward: None,
+            prior,
+        });
+        if node == self.root() {
+            if let Some(priors) = &mut self.noiseless_root_priors {
+                for other in priors.iter_mut() {
+                    *other *= shrink;
+                }
+                priors.push(prior);
             }
         }
-        // todo create new, as different determinisations can have different legal actions
-        panic!("Edge not found")
+        edge
+    }
+
+    pub(crate) fn set_action_reward(&mut self, edge: EdgeId, reward: f32) {
+        self.edges[edge.0].action_reward = Some(reward);
+    }
+
+    // Turns the outgoing edges of a node into transitions with the probabilities of the outcomes
+    pub(crate) fn set_transitions(&mut self, node: NodeId, outcomes: &[(A, f32)]) {
+        self.nodes[node.0].chance = true;
+        for ix in 0..self.edges(node).len() {
+            let edge = self.nodes[node.0].outgoing_edges[ix];
+            let label = &self.edges[edge.0].label;
+            self.edges[edge.0].prior = outcomes
+                .iter()
+                .find(|(outcome, _)| outcome == label)
+                .map_or(0.0, |(_, probability)| *probability);
+        }
     }
 
-    pub(crate) fn edges(&self) -> &Vec<Edge<L, A>> {
-        &self.outgoing_edges
+    // Priors of the outgoing edges of a node, in the same order as the edges
+    pub(crate) fn set_priors(&mut self, node: NodeId, priors: &[f32]) {
+        assert_eq!(self.edges(node).len(), priors.len(), "Expected a prior for every edge.");
+        for (ix, prior) in priors.iter().enumerate() {
+            let edge = self.nodes[node.0].outgoing_edges[ix];
+            self.edges[edge.0].prior = *prior;
+        }
+        if node == self.root() {
+            self.noiseless_root_priors = None;
+        }
     }
 
-    pub(crate) fn get_statistics_lock<'a, 'b: 'a>(&'b self) -> RefMut<'a, NodeStatistics> {
-        self.node_statistics.borrow_mut()
+    // Sets the priors of the root to (1 - fraction) * prior + fraction * noise, always mixing
+    // with the priors from before any noise, so that searching the same root again doesn't
+    // compound the noise
+    pub(crate) fn add_root_noise(&mut self, noise: &[f32], fraction: f32) {
+        let root = self.root();
+        let priors = match self.noiseless_root_priors.take() {
+            Some(priors) => priors,
+            None => self.edges(root).iter().map(|e| self.edge(*e).prior).collect(),
+        };
+        let noisy: Vec<f32> = priors.iter().zip(noise).map(|(prior, noise)| (1.0 - fraction) * prior + fraction * noise).collect();
+        self.set_priors(root, &noisy);
+        self.noiseless_root_priors = Some(priors);
     }
 
-    pub(crate) fn get_incoming_edge(&self) -> Option<&Edge<L, A>> {
-        unsafe {
-            self.incoming_edge.as_ref()
+    // Copies the subtree below the given node into a new tree, with that node as the root.
+    // Used to keep the relevant part of the tree after a move is played.
+    pub(crate) fn subtree(&self, node: NodeId) -> Tree<L, A> where L: Clone, A: Clone {
+        let mut result = Tree {
+            nodes: vec![],
+            edges: vec![],
+            min_max_stats: Mutex::new(self.get_min_max_stats()),
+            noiseless_root_priors: None,
+        };
+        let mut stack = vec![(node, None)];
+        while let Some((old_node, incoming_edge)) = stack.pop() {
+            let new_node = NodeId(result.nodes.len());
+            let mut outgoing_edges = vec![];
+            for old_edge in self.edges(old_node) {
+                let new_edge = EdgeId(result.edges.len());
+                let mut edge = self.edge(*old_edge).clone();
+                edge.parent_node = new_node;
+                if let Some(target_node) = edge.target_node.take() {
+                    stack.push((target_node, Some(new_edge)));
+                }
+                result.edges.push(edge);
+                outgoing_edges.push(new_edge);
+            }
+            result.nodes.push(Node {
+                label: self.node(old_node).label.clone(),
+                chance: self.node(old_node).chance,
+                incoming_edge,
+                outgoing_edges,
+                node_statistics: Mutex::new(self.get_statistics_lock(old_node).clone())
+            });
+            if let Some(edge) = incoming_edge {
+                result.edges[edge.0].target_node = Some(new_node);
+            }
+        }
+        result
+    }
+
+    // Adds the statistics of the root and its children from another tree
+    // that was searched from the same state
+    pub(crate) fn merge_root(&mut self, other: &Tree<L, A>) where L: Copy, A: Copy {
+        let root = self.root();
+        self.min_max_stats.lock().unwrap().merge(&other.get_min_max_stats());
+        self.get_statistics_lock(root).merge(&other.get_statistics_lock(other.root()));
+        for other_edge in other.edges(other.root()) {
+            let other_edge = other.edge(*other_edge);
+            if other_edge.is_dangling() {
+                continue
+            }
+            let other_child = other_edge.get_target_node();
+            let edge = match self.find_edge(root, &other_edge.label) {
+                Some(edge) => edge,
+                None => self.add_edge(root, other_edge.label),
+            };
+            if self.edge(edge).is_dangling() {
+                let actions = other.edges(other_child).iter().map(|e| other.edge(*e).label).collect();
+                if let Some(reward) = other_edge.get_action_reward() {
+                    self.set_action_reward(edge, reward);
+                }
+                let child = self.create_child(edge, other.node(other_child).label, actions);
+                let priors: Vec<f32> = other.edges(other_child).iter().map(|e| other.edge(*e).get_prior()).collect();
+                self.set_priors(child, &priors);
+                self.nodes[child.0].chance = other.node(other_child).chance;
+            }
+            let child = self.edge(edge).get_target_node();
+            self.get_statistics_lock(child).merge(&other.get_statistics_lock(other_child));
         }
     }
 }
 
+impl<L, A> SharedTree<L, A> {
+    pub(crate) fn new(tree: Tree<L, A>) -> Self {
+        SharedTree(RwLock::new(tree))
+    }
 
-impl <L, A> Edge<L, A> where A: PartialEq {
-    pub(crate) fn is_dangling(&self) -> bool {
-        self.target_node.is_none()
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, Tree<L, A>> {
+        self.0.read().unwrap()
     }
 
-    pub(crate) fn get_target_node(&self) -> &Node<L, A> {
-        self.target_node.as_ref().unwrap()
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, Tree<L, A>> {
+        self.0.write().unwrap()
     }
 
-    pub(crate) fn get_incoming_node(&self) -> &Node<L, A> {
-        unsafe {
-            &*self.parent_node
+    // Looks up an edge, adding it when the action is new to the node
+    pub(crate) fn get_or_add_edge(&self, node: NodeId, label: &A) -> EdgeId where A: Copy + PartialEq {
+        let edge = self.read().find_edge(node, label);
+        match edge {
+            Some(edge) => edge,
+            None => {
+                let mut tree = self.write();
+                // another search might have added it in the meantime
+                match tree.find_edge(node, label) {
+                    Some(edge) => edge,
+                    None => tree.add_edge(node, *label),
+                }
+            }
         }
     }
 
-    pub(crate) fn create_child(&self, label: L, actions: Vec<A>) {
-        assert!(self.is_dangling(), "Cannot rewrite.");
-        let mut target_node =  Node::new(label, actions, self);
+    pub(crate) fn into_inner(self) -> Tree<L, A> {
+        self.0.into_inner().unwrap()
+    }
+}
 
-        unsafe {
-            let target_node_ptr: *const Option<Node<L, A>> = &self.target_node;
-            let target_node_ptr_mut = target_node_ptr as *mut Option<Node<L, A>>;
-            let mut target_node_mut = &mut *target_node_ptr_mut;
-            target_node_mut.replace(target_node);
-        }
+impl<L> Node<L> {
+    pub(crate) fn is_chance(&self) -> bool {
+        self.chance
+    }
+
+    // we always create edges with all outgoing edges
+    // only edges can be dangling
+    pub(crate) fn is_terminal(&self) -> bool {
+        self.outgoing_edges.is_empty()
+    }
+}
+
+
+impl<A> Edge<A> {
+    pub(crate) fn is_dangling(&self) -> bool {
+        self.target_node.is_none()
+    }
+
+    pub(crate) fn get_target_node(&self) -> NodeId {
+        self.target_node.unwrap()
+    }
+
+    pub(crate) fn get_incoming_node(&self) -> NodeId {
+        self.parent_node
     }
 
     pub(crate) fn get_action_reward(&self) -> Option<f32> {
         self.action_reward
     }
 
-    pub(crate) fn set_action_reward(&self, reward: f32) {
-        unsafe {
-            let reward_ptr: *const Option<f32> = &self.action_reward;
-            let reward_ptr_mut = reward_ptr as *mut Option<f32>;
-            let mut reward_mut = &mut *reward_ptr_mut;
-            reward_mut.replace(reward);
-        }
+    pub(crate) fn get_prior(&self) -> f32 {
+        self.prior
     }
 }
 
 
+impl MinMaxStats {
+    pub(crate) fn new() -> Self {
+        MinMaxStats {
+            minimum: f32::MAX,
+            maximum: f32::MIN,
+        }
+    }
+
+    pub(crate) fn update(&mut self, value: f32) {
+        self.minimum = self.minimum.min(value);
+        self.maximum = self.maximum.max(value);
+    }
+
+    pub(crate) fn merge(&mut self, other: &MinMaxStats) {
+        self.minimum = self.minimum.min(other.minimum);
+        self.maximum = self.maximum.max(other.maximum);
+    }
+
+    // Values are left as they are until two different values have been seen
+    pub(crate) fn normalize(&self, value: f32) -> f32 {
+        if self.maximum > self.minimum {
+            (value - self.minimum) / (self.maximum - self.minimum)
+        } else {
+            value
+        }
+    }
+}
+
 impl NodeStatistics {
     fn new() -> Self {
         NodeStatistics {
             select_count: 0,
             sample_count: 0,
-            expected_reward: 0.0
+            expected_reward: 0.0,
+            virtual_loss_count: 0,
+            virtual_loss: 0.0,
+            availability_count: 0,
+            decoupled: None,
+            proven: None,
+            amaf: vec![],
         }
     }
 
-    fn select_count(&self) -> u32 {
+    pub(crate) fn select_count(&self) -> u32 {
         self.select_count
     }
+
     pub(crate) fn increment_select_count(&mut self) {
         self.select_count += 1
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn availability_count(&self) -> u32 {
+        self.availability_count
+    }
+
+    pub(crate) fn increment_availability_count(&mut self) {
+        self.availability_count += 1
+    }
+
+    // None until the node is selected as a simultaneous move
+    pub(crate) fn decoupled(&self) -> Option<&DecoupledStatistics> {
+        self.decoupled.as_deref()
+    }
+
+    pub(crate) fn decoupled_mut(&mut self) -> Option<&mut DecoupledStatistics> {
+        self.decoupled.as_deref_mut()
+    }
+
+    pub(crate) fn decoupled_or_default(&mut self) -> &mut DecoupledStatistics {
+        self.decoupled.get_or_insert_with(Default::default)
+    }
+
+    pub(crate) fn proven(&self) -> Option<&Proven> {
+        self.proven.as_deref()
+    }
+
+    pub(crate) fn prove(&mut self, proven: Proven) {
+        self.proven = Some(Box::new(proven))
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn amaf(&self, position: usize) -> (u32, f32) {
+        self.amaf.get(position).copied().unwrap_or((0, 0.0))
+    }
+
+    pub(crate) fn add_amaf_sample(&mut self, position: usize, value: f32, weight: u32) {
+        if self.amaf.len() <= position {
+            self.amaf.resize(position + 1, (0, 0.0));
+        }
+        let (count, mean) = &mut self.amaf[position];
+        *count += weight;
+        *mean += weight as f32 * (value - *mean) / *count as f32;
+    }
+
+    pub(crate) fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Samples including pending virtual visits
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn visit_count(&self) -> u32 {
+        self.sample_count + self.virtual_loss_count
+    }
+
     pub(crate) fn add_sample(&mut self, s: f32, weight: u32) {
         self.expected_reward += (weight as f32) * (s - self.expected_reward) / (weight + self.sample_count) as f32;
         self.sample_count += weight
     }
 
-    pub(crate)fn expected_sample(&self) -> f32 {
-        self.expected_reward
+    pub(crate) fn merge(&mut self, other: &NodeStatistics) {
+        self.select_count += other.select_count;
+        self.availability_count += other.availability_count;
+        if let Some(other) = &other.decoupled {
+            self.decoupled_or_default().merge(other);
+        }
+        if self.proven.is_none() {
+            self.proven = other.proven.clone();
+        }
+        for (position, (count, mean)) in other.amaf.iter().enumerate() {
+            if *count > 0 {
+                self.add_amaf_sample(position, *mean, *count)
+            }
+        }
+        if other.sample_count > 0 {
+            self.add_sample(other.expected_reward, other.sample_count)
+        }
+    }
+
+    pub(crate) fn add_virtual_loss(&mut self, loss: f32) {
+        self.virtual_loss_count += 1;
+        self.virtual_loss += loss
+    }
+
+    pub(crate) fn remove_virtual_loss(&mut self, loss: f32) {
+        self.virtual_loss_count -= 1;
+        self.virtual_loss -= loss
+    }
+
+    // Includes the pending virtual losses, so concurrent searches spread out
+    pub(crate) fn expected_sample(&self) -> f32 {
+        if self.virtual_loss_count == 0 {
+            self.expected_reward
+        } else {
+            (self.expected_reward * self.sample_count as f32 - self.virtual_loss)
+                / (self.sample_count + self.virtual_loss_count) as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::search::EdgeLabel;
+    use crate::lib::search::tree::{MinMaxStats, Tree};
+
+    #[test]
+    fn parent_links_survive_moves() {
+        let mut tree = Tree::new(0, vec![1, 2, 3]);
+        let root = tree.root();
+        let edge = tree.get_edge(root, &2);
+        let child = tree.create_child(edge, 1, vec![4, 5]);
+
+        // moving the tree must not affect the links
+        let boxed = Box::new(tree);
+        let tree = *boxed;
+
+        assert_eq!(tree.get_parent(child), Some(root));
+        assert_eq!(tree.get_incoming_edge(child), Some(edge));
+        assert_eq!(tree.edge(edge).get_target_node(), child);
+        assert_eq!(tree.get_parent(root), None);
+        assert_eq!(tree.edges(child).len(), 2);
+        assert!(tree.edge(tree.get_edge(root, &1)).is_dangling());
+    }
+
+    #[test]
+    fn priors() {
+        let mut tree = Tree::new(0, vec![1, 2]);
+        let root = tree.root();
+        assert_eq!(tree.edge(tree.get_edge(root, &1)).get_prior(), 0.5);
+        tree.set_priors(root, &[0.25, 0.75]);
+        assert_eq!(tree.edge(tree.get_edge(root, &2)).get_prior(), 0.75);
+        let subtree = tree.subtree(root);
+        assert_eq!(subtree.edge(subtree.get_edge(root, &1)).get_prior(), 0.25);
+    }
+
+    #[test]
+    fn statistics() {
+        let tree = Tree::new(0, vec![1]);
+        let mut stats = tree.get_statistics_lock(tree.root());
+        stats.add_sample(2.0, 1);
+        stats.add_sample(4.0, 1);
+        stats.increment_select_count();
+        assert_eq!(stats.sample_count(), 2);
+        assert_eq!(stats.select_count(), 1);
+        assert_eq!(stats.expected_sample(), 3.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn merge_root() {
+        let mut tree = Tree::new(0, vec![1, 2]);
+        let mut other = Tree::new(0, vec![1, 2]);
+        let edge = other.get_edge(other.root(), &2);
+        other.set_action_reward(edge, 1.0);
+        let child = other.create_child(edge, 1, vec![3]);
+        other.get_statistics_lock(child).add_sample(5.0, 2);
+        other.get_statistics_lock(other.root()).add_sample(5.0, 2);
+        tree.get_statistics_lock(tree.root()).add_sample(2.0, 1);
+
+        tree.merge_root(&other);
+
+        let root_stats = tree.get_statistics_lock(tree.root());
+        assert_eq!(root_stats.sample_count(), 3);
+        assert_eq!(root_stats.expected_sample(), 4.0);
+        let edge = tree.edge(tree.get_edge(tree.root(), &2));
+        assert_eq!(edge.get_action_reward(), Some(1.0));
+        assert_eq!(tree.get_statistics_lock(edge.get_target_node()).sample_count(), 2);
+        assert_eq!(tree.edges(edge.get_target_node()).len(), 1);
+        assert!(tree.edge(tree.get_edge(tree.root(), &1)).is_dangling());
+    }
+
+    #[test]
+    fn subtree() {
+        let mut tree = Tree::new(0, vec![1, 2]);
+        let edge = tree.get_edge(tree.root(), &2);
+        let child = tree.create_child(edge, 1, vec![3, 4]);
+        let edge = tree.get_edge(child, &4);
+        tree.set_action_reward(edge, 2.0);
+        let grandchild = tree.create_child(edge, 0, vec![5]);
+        tree.get_statistics_lock(child).add_sample(3.0, 2);
+        tree.get_statistics_lock(grandchild).add_sample(1.0, 1);
+
+        let subtree = tree.subtree(child);
+        let root = subtree.root();
+        assert_eq!(subtree.node_count(), 2);
+        assert_eq!(subtree.node(root).label, 1);
+        assert_eq!(subtree.get_incoming_edge(root), None);
+        assert_eq!(subtree.get_statistics_lock(root).sample_count(), 2);
+        assert!(subtree.edge(subtree.get_edge(root, &3)).is_dangling());
+
+        let edge = subtree.edge(subtree.get_edge(root, &4));
+        assert_eq!(edge.get_action_reward(), Some(2.0));
+        let grandchild = edge.get_target_node();
+        assert_eq!(subtree.get_parent(grandchild), Some(root));
+        assert_eq!(subtree.get_statistics_lock(grandchild).expected_sample(), 1.0);
+        assert_eq!(subtree.edges(grandchild).len(), 1);
+    }
+
+    #[test]
+    fn transitions() {
+        let mut tree = Tree::new(0, vec![1, 2, 3]);
+        let root = tree.root();
+        assert_eq!(tree.edge_label(tree.get_edge(root, &1)), EdgeLabel::Action(1));
+        assert_eq!(tree.chance_value(root), None);
+
+        tree.set_transitions(root, &[(2, 0.25), (1, 0.75)]);
+        assert!(tree.node(root).is_chance());
+        assert_eq!(tree.edge_label(tree.get_edge(root, &1)), EdgeLabel::Transition((1, 0.75)));
+        assert_eq!(tree.edge_label(tree.get_edge(root, &3)), EdgeLabel::Transition((3, 0.0)));
+
+        for (outcome, value) in [(1, 4.0), (2, 8.0)] {
+            let edge = tree.get_edge(root, &outcome);
+            let child = tree.create_child(edge, 1, vec![]);
+            tree.get_statistics_lock(child).add_sample(value, 1);
+        }
+        assert_eq!(tree.chance_value(root), Some(5.0));
+        assert!(tree.subtree(root).node(root).is_chance());
+    }
+
+    #[test]
+    fn add_edge() {
+        let mut tree = Tree::new(0, vec![1]);
+        let root = tree.root();
+        let child = tree.create_child(tree.get_edge(root, &1), 1, vec![]);
+        tree.get_statistics_lock(child).increment_availability_count();
+
+        let edge = tree.add_edge(root, 2);
+        assert_eq!(tree.find_edge(root, &2), Some(edge));
+        assert_eq!(tree.edge(edge).get_prior(), 0.5);
+        assert!(tree.edge(edge).is_dangling());
+        // the priors still sum to 1
+        tree.add_edge(root, 3);
+        let priors: Vec<f32> = tree.edges(root).iter().map(|e| tree.edge(*e).get_prior()).collect();
+        assert_eq!(priors.iter().sum::<f32>(), 1.0);
+        assert!((priors[0] - 1.0 / 3.0).abs() < 1e-6 && priors[1] == priors[0]);
+        // the existing edge still leads to the same child
+        assert_eq!(tree.edge(tree.get_edge(root, &1)).get_target_node(), child);
+        assert_eq!(tree.subtree(root).get_statistics_lock(child).availability_count(), 1);
+    }
+
+    #[test]
+    fn min_max_stats() {
+        let mut stats = MinMaxStats::new();
+        assert_eq!(stats.normalize(7.0), 7.0);
+        stats.update(100.0);
+        assert_eq!(stats.normalize(7.0), 7.0);
+        stats.update(300.0);
+        assert_eq!(stats.normalize(200.0), 0.5);
+        assert_eq!(stats.normalize(300.0), 1.0);
+    }
+
+    #[test]
+    fn virtual_loss() {
+        let tree = Tree::new(0, vec![1]);
+        let mut stats = tree.get_statistics_lock(tree.root());
+        stats.add_sample(4.0, 1);
+        stats.add_virtual_loss(2.0);
+        assert_eq!(stats.expected_sample(), 1.0);
+        stats.remove_virtual_loss(2.0);
+        assert_eq!(stats.expected_sample(), 4.0);
+        assert_eq!(stats.sample_count(), 1);
+    }
+}
@@ -24,8 +24,44 @@ pub trait SearchProblem {
         action: &Self::Action,
         player: &Self::Player
     ) -> Self::Action;
+
+    // The outcomes and their probabilities when the state is decided by chance,
+    // None when the current actor chooses
+    fn chance_outcomes(&self, _state: &Self::HiddenState) -> Option<Vec<(Self::Action, f32)>> {
+        None
+    }
+
+    // The action that lets the current actor skip its turn, if the game has one.
+    // Used by best-reply search to let a single opponent move at a time.
+    fn pass_action(&self, _state: &Self::HiddenState) -> Option<Self::Action> {
+        None
+    }
+
+    // The largest return the player can get from any state, if known. In a state where an action
+    // is proven to get it, the player has a proven win and the other actions need no search.
+    fn max_return(&self, _player: Self::Player) -> Option<f32> {
+        None
+    }
+
+    // How several players move at the same time, None when the players always move one at a time
+    fn simultaneous(&self) -> Option<&dyn Simultaneous<Self>> where Self: Sized {
+        None
+    }
+}
+
+// A problem where several players can move at the same time
+pub trait Simultaneous<P: SearchProblem> {
+    // The actions of every player when several players move at the same time, None when the
+    // current actor moves alone. The current actor is one of the players moving, and its legal
+    // actions are all the joint actions.
+    fn moves(&self, state: &P::HiddenState) -> Option<SimultaneousMoves<P::Player, P::Action>>;
+
+    // The joint action made of an action of every player moving, in the order of the moves
+    fn joint_action(&self, state: &P::HiddenState, actions: &[P::Action]) -> P::Action;
 }
 
+// The legal actions of every player moving at the same time
+pub type SimultaneousMoves<Player, Action> = Vec<(Player, Vec<Action>)>;
 
 pub trait Observation<Player: Copy, Action> {
     // last reward for current
@@ -0,0 +1,632 @@
+// Small games the tests search, shared by the tests of all the modules
+use rand::{Rng, RngCore};
+use crate::lib::search::{Determinizer, PriorProvider};
+use crate::lib::search_problem::{HiddenState, Observation, SearchProblem, Simultaneous};
+use crate::lib::tzf8::{Action, Board, TwoZeroFourEight};
+
+// A single player walks down a line, receiving a reward of 1 on every step
+pub(crate) struct Line {
+    pub(crate) length: u32,
+}
+
+#[derive(Clone)]
+pub(crate) struct Position {
+    pub(crate) position: u32,
+    pub(crate) length: u32,
+}
+
+impl SearchProblem for Line {
+    type HiddenState = Position;
+    type Action = u8;
+    type Observation = Position;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Position, _: u8) -> &'a Position {
+        state
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0]
+    }
+
+    fn get_visible_action(&self, _: &Position, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+}
+
+impl Observation<u8, u8> for Position {
+    fn reward(&self) -> f32 {
+        if self.position > 0 { 1.0 } else { 0.0 }
+    }
+
+    fn legal_actions(&self) -> Vec<u8> {
+        if self.is_terminal() { vec![] } else { vec![0] }
+    }
+}
+
+impl HiddenState<u8, u8> for Position {
+    fn apply(&self, _: &u8) -> Self {
+        Position {
+            position: self.position + 1,
+            length: self.length,
+        }
+    }
+
+    fn current_actor(&self) -> u8 {
+        0
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.position == self.length
+    }
+}
+
+// The player decides to flip a coin that lands on the winning side with probability 0.9
+pub(crate) struct Coin;
+
+#[derive(Clone)]
+pub(crate) struct Flip {
+    pub(crate) stage: u8,
+    pub(crate) reward: f32,
+}
+
+impl SearchProblem for Coin {
+    type HiddenState = Flip;
+    type Action = u8;
+    type Observation = Flip;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Flip, _: u8) -> &'a Flip {
+        state
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0, 1]
+    }
+
+    fn get_visible_action(&self, _: &Flip, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+
+    fn chance_outcomes(&self, state: &Flip) -> Option<Vec<(u8, f32)>> {
+        if state.stage == 1 {
+            Some(vec![(1, 0.9), (2, 0.1)])
+        } else {
+            None
+        }
+    }
+}
+
+impl Observation<u8, u8> for Flip {
+    fn reward(&self) -> f32 {
+        self.reward
+    }
+
+    fn legal_actions(&self) -> Vec<u8> {
+        match self.stage {
+            0 => vec![0],
+            1 => vec![1, 2],
+            _ => vec![],
+        }
+    }
+}
+
+impl HiddenState<u8, u8> for Flip {
+    fn apply(&self, action: &u8) -> Self {
+        Flip {
+            stage: self.stage + 1,
+            reward: if *action == 1 { 1.0 } else { 0.0 },
+        }
+    }
+
+    fn current_actor(&self) -> u8 {
+        if self.stage == 1 { 1 } else { 0 }
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.stage == 2
+    }
+}
+
+// Three players move once each: player 0 picks a side (1 or 2), player 1 splits the payoff
+// (0 passes, 1 and 2 split it) and player 2 passes (0) or attacks player 0 (1)
+pub(crate) struct Trio;
+
+#[derive(Clone)]
+pub(crate) struct Seen {
+    pub(crate) reward: f32,
+    pub(crate) actions: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Table {
+    pub(crate) moves: Vec<u8>,
+    // what every player sees
+    pub(crate) seen: [Seen; 3],
+}
+
+impl Table {
+    pub(crate) fn start() -> Self {
+        let seen = Seen { reward: 0.0, actions: vec![1, 2] };
+        Table {
+            moves: vec![],
+            seen: [seen.clone(), seen.clone(), seen],
+        }
+    }
+
+    pub(crate) fn payoff(&self) -> [f32; 3] {
+        let mut payoff = match (self.moves[0], self.moves[1]) {
+            (1, 1) => [5.0, 5.0, 0.0],
+            (1, 2) => [0.0, 4.0, 10.0],
+            (1, _) => [2.0, 2.0, 2.0],
+            _ => [3.0, 3.0, 3.0],
+        };
+        if self.moves[2] == 1 {
+            payoff[0] -= 10.0;
+            payoff[2] += 1.0;
+        }
+        payoff
+    }
+}
+
+impl SearchProblem for Trio {
+    type HiddenState = Table;
+    type Action = u8;
+    type Observation = Seen;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Table, player: u8) -> &'a Seen {
+        &state.seen[player as usize]
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0, 1, 2]
+    }
+
+    fn get_visible_action(&self, _: &Table, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+
+    fn pass_action(&self, state: &Table) -> Option<u8> {
+        if state.current_actor() == 0 { None } else { Some(0) }
+    }
+}
+
+impl Observation<u8, u8> for Seen {
+    fn reward(&self) -> f32 {
+        self.reward
+    }
+
+    fn legal_actions(&self) -> Vec<u8> {
+        self.actions.clone()
+    }
+}
+
+impl HiddenState<u8, u8> for Table {
+    fn apply(&self, action: &u8) -> Self {
+        let mut next = self.clone();
+        next.moves.push(*action);
+        let (actions, payoff) = match next.moves.len() {
+            1 => (vec![0, 1, 2], [0.0; 3]),
+            2 => (vec![0, 1], [0.0; 3]),
+            _ => (vec![], next.payoff()),
+        };
+        // all the players see the legal actions of the player to move
+        for (seen, reward) in next.seen.iter_mut().zip(payoff) {
+            seen.actions = actions.clone();
+            seen.reward = reward;
+        }
+        next
+    }
+
+    fn current_actor(&self) -> u8 {
+        self.moves.len() as u8 % 3
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.moves.len() == 3
+    }
+}
+
+// Player 1 holds a card (1 or 2) that player 0 can't see, player 0 gets its value
+// once player 1 has played it. Player 1 can only play the card it holds.
+pub(crate) struct Cards;
+
+#[derive(Clone)]
+pub(crate) struct Hand {
+    pub(crate) card: u8,
+    pub(crate) moves: Vec<u8>,
+    pub(crate) seen: Seen,
+}
+
+impl Hand {
+    pub(crate) fn deal(card: u8) -> Self {
+        Hand {
+            card,
+            moves: vec![],
+            seen: Seen { reward: 0.0, actions: vec![0] },
+        }
+    }
+}
+
+impl SearchProblem for Cards {
+    type HiddenState = Hand;
+    type Action = u8;
+    type Observation = Seen;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Hand, _: u8) -> &'a Seen {
+        &state.seen
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0, 1]
+    }
+
+    fn get_visible_action(&self, _: &Hand, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+}
+
+impl HiddenState<u8, u8> for Hand {
+    fn apply(&self, action: &u8) -> Self {
+        let mut next = self.clone();
+        next.moves.push(*action);
+        next.seen = match next.moves.len() {
+            1 => Seen { reward: 0.0, actions: vec![self.card] },
+            _ => Seen { reward: *action as f32, actions: vec![] },
+        };
+        next
+    }
+
+    fn current_actor(&self) -> u8 {
+        self.moves.len() as u8
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.moves.len() == 2
+    }
+}
+
+// Player 0 only knows that the card is 1 or 2
+pub(crate) struct Deal;
+
+impl Determinizer<Cards> for Deal {
+    fn determinize(&self, _: &Cards, hidden_state: &Hand, _: u8, rng: &mut dyn RngCore) -> Hand {
+        let mut result = hidden_state.clone();
+        result.card = rng.gen_range(1..=2);
+        result
+    }
+}
+
+// Both players choose at the same time to cooperate (0) or defect (1), defecting is dominant.
+// Joint actions are 2 + 2 * the action of player 0 + the action of player 1.
+pub(crate) struct Dilemma;
+
+#[derive(Clone)]
+pub(crate) struct Choices {
+    pub(crate) joint_action: Option<u8>,
+    pub(crate) seen: [Seen; 2],
+}
+
+impl Choices {
+    pub(crate) fn start() -> Self {
+        let seen = Seen { reward: 0.0, actions: vec![2, 3, 4, 5] };
+        Choices {
+            joint_action: None,
+            seen: [seen.clone(), seen],
+        }
+    }
+}
+
+impl SearchProblem for Dilemma {
+    type HiddenState = Choices;
+    type Action = u8;
+    type Observation = Seen;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Choices, player: u8) -> &'a Seen {
+        &state.seen[player as usize]
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0, 1]
+    }
+
+    fn get_visible_action(&self, _: &Choices, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+
+    fn simultaneous(&self) -> Option<&dyn Simultaneous<Self>> {
+        Some(self)
+    }
+}
+
+impl Simultaneous<Dilemma> for Dilemma {
+    fn moves(&self, state: &Choices) -> Option<Vec<(u8, Vec<u8>)>> {
+        if state.is_terminal() { None } else { Some(vec![(0, vec![0, 1]), (1, vec![0, 1])]) }
+    }
+
+    fn joint_action(&self, _: &Choices, actions: &[u8]) -> u8 {
+        2 + 2 * actions[0] + actions[1]
+    }
+}
+
+impl HiddenState<u8, u8> for Choices {
+    fn apply(&self, action: &u8) -> Self {
+        let payoff = match action {
+            2 => [3.0, 3.0],
+            3 => [0.0, 5.0],
+            4 => [5.0, 0.0],
+            _ => [1.0, 1.0],
+        };
+        Choices {
+            joint_action: Some(*action),
+            seen: payoff.map(|reward| Seen { reward, actions: vec![] }),
+        }
+    }
+
+    fn current_actor(&self) -> u8 {
+        0
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.joint_action.is_some()
+    }
+}
+
+// Players take 1 or 2 sticks in turns, the one taking the last stick wins.
+// Leaving a multiple of 3 sticks wins.
+pub(crate) struct Nim;
+
+#[derive(Clone)]
+pub(crate) struct Sticks {
+    pub(crate) sticks: u8,
+    pub(crate) player: u8,
+    pub(crate) seen: [Seen; 2],
+}
+
+impl Sticks {
+    pub(crate) fn new(sticks: u8) -> Self {
+        let seen = Seen { reward: 0.0, actions: (1..=sticks.min(2)).collect() };
+        Sticks {
+            sticks,
+            player: 0,
+            seen: [seen.clone(), seen],
+        }
+    }
+}
+
+impl SearchProblem for Nim {
+    type HiddenState = Sticks;
+    type Action = u8;
+    type Observation = Seen;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Sticks, player: u8) -> &'a Seen {
+        &state.seen[player as usize]
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0, 1]
+    }
+
+    fn get_visible_action(&self, _: &Sticks, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+
+    fn max_return(&self, _: u8) -> Option<f32> {
+        Some(1.0)
+    }
+}
+
+impl HiddenState<u8, u8> for Sticks {
+    fn apply(&self, action: &u8) -> Self {
+        let sticks = self.sticks - action;
+        let actions: Vec<u8> = (1..=sticks.min(2)).collect();
+        let mut seen = [0.0, 0.0].map(|reward| Seen { reward, actions: actions.clone() });
+        if sticks == 0 {
+            seen[self.player as usize].reward = 1.0;
+            seen[1 - self.player as usize].reward = -1.0;
+        }
+        Sticks {
+            sticks,
+            player: 1 - self.player,
+            seen,
+        }
+    }
+
+    fn current_actor(&self) -> u8 {
+        self.player
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.sticks == 0
+    }
+}
+
+// A single player picks one of 1, 2 and 3 three times, winning if it ever picks 3
+pub(crate) struct Picks;
+
+#[derive(Clone)]
+pub(crate) struct Picked {
+    pub(crate) picked: Vec<u8>,
+    pub(crate) seen: Seen,
+}
+
+impl Picked {
+    pub(crate) fn start() -> Self {
+        Picked {
+            picked: vec![],
+            seen: Seen { reward: 0.0, actions: vec![1, 2, 3] },
+        }
+    }
+}
+
+impl SearchProblem for Picks {
+    type HiddenState = Picked;
+    type Action = u8;
+    type Observation = Seen;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Picked, _: u8) -> &'a Seen {
+        &state.seen
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0]
+    }
+
+    fn get_visible_action(&self, _: &Picked, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+}
+
+impl HiddenState<u8, u8> for Picked {
+    fn apply(&self, action: &u8) -> Self {
+        let mut picked = self.picked.clone();
+        picked.push(*action);
+        let seen = if picked.len() == 3 {
+            Seen { reward: if picked.contains(&3) { 1.0 } else { 0.0 }, actions: vec![] }
+        } else {
+            Seen { reward: 0.0, actions: vec![1, 2, 3] }
+        };
+        Picked { picked, seen }
+    }
+
+    fn current_actor(&self) -> u8 {
+        0
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.picked.len() == 3
+    }
+}
+
+// A player picks a digit, chance rolls one, and the player picks another, receiving a tenth of its picks
+pub(crate) struct Digits;
+
+#[derive(Clone)]
+pub(crate) struct Roll {
+    pub(crate) stage: u8,
+    pub(crate) reward: f32,
+}
+
+impl SearchProblem for Digits {
+    type HiddenState = Roll;
+    type Action = u8;
+    type Observation = Roll;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Roll, _: u8) -> &'a Roll {
+        state
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0, 1]
+    }
+
+    fn get_visible_action(&self, _: &Roll, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+
+    fn chance_outcomes(&self, state: &Roll) -> Option<Vec<(u8, f32)>> {
+        if state.stage == 1 {
+            Some((0..10).map(|digit| (digit, 0.1)).collect())
+        } else {
+            None
+        }
+    }
+}
+
+impl Observation<u8, u8> for Roll {
+    fn reward(&self) -> f32 {
+        self.reward
+    }
+
+    fn legal_actions(&self) -> Vec<u8> {
+        if self.is_terminal() { vec![] } else { (0..10).collect() }
+    }
+}
+
+impl HiddenState<u8, u8> for Roll {
+    fn apply(&self, action: &u8) -> Self {
+        Roll {
+            stage: self.stage + 1,
+            reward: if self.stage == 1 { 0.0 } else { *action as f32 / 10.0 },
+        }
+    }
+
+    fn current_actor(&self) -> u8 {
+        if self.stage == 1 { 1 } else { 0 }
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.stage == 3
+    }
+}
+
+// One pull of one of five arms, arm i pays i / 4
+pub(crate) struct Bandit;
+
+#[derive(Clone)]
+pub(crate) struct Pull {
+    pub(crate) arm: Option<u8>,
+}
+
+impl SearchProblem for Bandit {
+    type HiddenState = Pull;
+    type Action = u8;
+    type Observation = Pull;
+    type Player = u8;
+
+    fn get_observation<'a>(&self, state: &'a Pull, _: u8) -> &'a Pull {
+        state
+    }
+
+    fn get_all_players(&self) -> Vec<u8> {
+        vec![0]
+    }
+
+    fn get_visible_action(&self, _: &Pull, action: &u8, _: &u8) -> u8 {
+        *action
+    }
+}
+
+impl Observation<u8, u8> for Pull {
+    fn reward(&self) -> f32 {
+        self.arm.map_or(0.0, |arm| arm as f32 / 4.0)
+    }
+
+    fn legal_actions(&self) -> Vec<u8> {
+        if self.is_terminal() { vec![] } else { (0..5).collect() }
+    }
+}
+
+impl HiddenState<u8, u8> for Pull {
+    fn apply(&self, action: &u8) -> Self {
+        Pull { arm: Some(*action) }
+    }
+
+    fn current_actor(&self) -> u8 {
+        0
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.arm.is_some()
+    }
+}
+
+// Prefers keeping the tiles in the bottom left corner
+pub(crate) struct CornerPrior;
+
+impl PriorProvider<TwoZeroFourEight> for CornerPrior {
+    fn priors(&self, _: &TwoZeroFourEight, _: &Board, actions: &[Action]) -> Vec<f32> {
+        let weights: Vec<f32> = actions.iter().map(|a| match a {
+            Action::Left | Action::Down => 4.0,
+            _ => 1.0,
+        }).collect();
+        let total: f32 = weights.iter().sum();
+        weights.iter().map(|w| w / total).collect()
+    }
+}
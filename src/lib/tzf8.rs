@@ -1,19 +1,19 @@
 use std::fmt::{Display, Formatter};
-use std::ptr::write;
-use rand::Rng;
+use rand::RngCore;
 use rand::seq::SliceRandom;
 use crate::lib::search::TreePolicy;
-use crate::lib::search::tree::{Node, Edge};
+use crate::lib::search::policy::sample_transition;
+use crate::lib::search::tree::{EdgeId, NodeId, Tree};
 use crate::lib::search_problem::{Observation, SearchProblem};
 use crate::lib::search_problem::HiddenState;
 use crate::lib::Simulator;
 
-struct TwoZeroFourEight {
+pub(crate) struct TwoZeroFourEight {
 }
 
-#[derive(Clone)]
-struct Board {
-    cells: [[u32; 4]; 4],
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Board {
+    pub(crate) cells: [[u32; 4]; 4],
     // total of all the newly created tiles
     new_tile_sum: u32,
     // if this state is terminal
@@ -23,13 +23,13 @@ struct Board {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
-enum Player {
+pub(crate) enum Player {
     Environment,
     Agent
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
-enum Action {
+pub(crate) enum Action {
     Left,
     Right,
     Up,
@@ -38,7 +38,7 @@ enum Action {
     Place(usize, usize) // Move of environment player
 }
 
-const ALL_ACTIONS: [Action; 4] = [Action::Left, Action::Right, Action::Up, Action::Down];
+pub(crate) const ALL_ACTIONS: [Action; 4] = [Action::Left, Action::Right, Action::Up, Action::Down];
 
 impl SearchProblem for TwoZeroFourEight {
     type HiddenState = Board;
@@ -65,7 +65,19 @@ impl SearchProblem for TwoZeroFourEight {
         _: &Self::Player
     ) -> Self::Action {
         // No partially observable actions
-        action.clone()
+        *action
+    }
+
+    fn chance_outcomes(&self, state: &Board) -> Option<Vec<(Action, f32)>> {
+        // The new tile is placed on any of the empty cells with the same probability
+        match state.current_actor() {
+            Player::Agent => None,
+            Player::Environment => {
+                let places = state.legal_actions();
+                let probability = 1.0 / places.len() as f32;
+                Some(places.into_iter().map(|place| (place, probability)).collect())
+            }
+        }
     }
 }
 
@@ -106,6 +118,8 @@ impl HiddenState<Player, Action> for Board {
                 let mut result = self.clone();
                 result.cells[*row][*col] = 2;
                 result.dropped = true;
+                // no tiles are merged by placing one, the reward of the move was already received
+                result.new_tile_sum = 0;
                 result
             }
         }
@@ -126,6 +140,14 @@ impl HiddenState<Player, Action> for Board {
 }
 
 impl Board {
+    // An empty board, the environment places the first tile
+    pub(crate) fn start() -> Board {
+        Board {
+            dropped: false,
+            ..Board::new()
+        }
+    }
+
     fn new() -> Board {
         Board{
             cells: [[0; 4]; 4],
@@ -140,8 +162,6 @@ impl Board {
         let mut changed = false;
         let mut new_tile_sum = 0;
 
-        let mut empty_cell_count: u32 = 0;
-
         for row in 0..4 {
             let mut pos = 0;
             let mut skip_flag = true;
@@ -152,8 +172,6 @@ impl Board {
                         new_tile_sum += result.cells[row][pos-1];
                         changed = true;
                         skip_flag = true;
-                        // one less cell in result
-                        empty_cell_count += 1;
                     } else {
                         result.cells[row][pos] = self.cells[row][col];
                         if pos != col {
@@ -162,8 +180,6 @@ impl Board {
                         pos += 1;
                         skip_flag = false;
                     }
-                } else {
-                    empty_cell_count += 1;
                 }
             }
         }
@@ -187,9 +203,7 @@ impl Board {
     fn flip_vertically(&mut self) {
         for row in 0..4 {
             for col in 0..2 {
-                let t = self.cells[row][col];
-                self.cells[row][col] = self.cells[row][3-col];
-                self.cells[row][3-col] = t;
+                self.cells[row].swap(col, 3-col);
             }
         }
     }
@@ -226,21 +240,29 @@ impl Display for Board {
             for col in 0..4 {
                 write!(f, "|{:5}", self.cells[row][col])?;
             }
-            write!(f, "|\n")?;
+            writeln!(f, "|")?;
         }
         if self.terminal {
-            write!(f, "<>, {}\n", self.new_tile_sum)?;
+            writeln!(f, "<>, {}", self.new_tile_sum)?;
         } else {
-            write!(f, "><, {}\n", self.new_tile_sum)?;
+            writeln!(f, "><, {}", self.new_tile_sum)?;
         }
         Ok(())
     }
 }
 
+#[cfg_attr(not(test), allow(dead_code))]
 struct TwoZeroFourEightSimulator;
 
 impl Simulator<TwoZeroFourEight> for TwoZeroFourEightSimulator {
-    fn simulate(&self, problem: &TwoZeroFourEight, state: Board, horizon: u32, discount: f32) -> Vec<(Player, f32)> {
+    fn simulate(
+        &self,
+        problem: &TwoZeroFourEight,
+        state: Board,
+        horizon: u32,
+        discount: f32,
+        rng: &mut dyn RngCore,
+    ) -> Vec<(Player, f32)> {
 
         assert!(state.dropped, "Environment player cannot be the agent to move");
 
@@ -253,24 +275,24 @@ impl Simulator<TwoZeroFourEight> for TwoZeroFourEightSimulator {
             //println!("{}: \n{}", index, current_state);
 
             let obs = problem.get_observation(&current_state,Player::Agent);
-            total_score += obs.reward() * discount_factor;
 
             if obs.is_terminal() {
                 break;
             }
 
             let mut actions = obs.legal_actions();
-            actions.shuffle(&mut rand::thread_rng());
+            actions.shuffle(rng);
             let mut all_terminal = true;
             'inner: for action in actions {
                 //println!("tried {:?}", action);
                 let current_state_temp = current_state.apply(&action);
                 if !current_state_temp.is_terminal() {
+                    total_score += current_state_temp.reward() * discount_factor;
                     current_state = current_state_temp;
                     all_terminal = false;
                     assert!(!current_state.dropped, "expected environment");
                     let environment_player_actions = current_state.legal_actions();
-                    let environment_player_action = environment_player_actions.choose(&mut rand::thread_rng()).unwrap();
+                    let environment_player_action = environment_player_actions.choose(rng).unwrap();
                     current_state = current_state.apply(environment_player_action);
                     break 'inner;
                 }
@@ -284,50 +306,55 @@ impl Simulator<TwoZeroFourEight> for TwoZeroFourEightSimulator {
     }
 }
 
-struct TwoZeroFourEightTreePolicy {
+pub(crate) struct TwoZeroFourEightTreePolicy {
 
 }
 
-impl<X> TreePolicy<Node<X, Action>, Board, Edge<X, Action>> for TwoZeroFourEightTreePolicy {
-    fn select_edge<'a>(&self, node: &'a Node<X, Action>, hidden_state: &Board) -> &'a Edge<X, Action> {
-        // fully observable, so no legal actions on a node do not change across determinisations
+impl<X> TreePolicy<X, Action, Board> for TwoZeroFourEightTreePolicy {
+    fn select_edge(
+        &self,
+        tree: &Tree<X, Action>,
+        _: NodeId,
+        edges: &[EdgeId],
+        hidden_state: &Board,
+        rng: &mut dyn RngCore,
+    ) -> EdgeId {
+        // fully observable, so the legal actions on a node do not change across determinisations
         match hidden_state.current_actor() {
             Player::Agent => {
-                let edges = node.edges();
                 let mut max_ucb_score = f32::MIN;
-                let mut best_edge = &edges[0];
-                for edge in edges.iter() {
+                let mut best_edge = edges[0];
+                for edge_id in edges.iter() {
+                    let edge = tree.edge(*edge_id);
                     if edge.is_dangling() {
-                        return edge
+                        return *edge_id
                     }
-                    let target_node_stats = edge.get_target_node().get_statistics_lock();
-                    let expected_reward = edge.get_action_reward().unwrap() + target_node_stats.expected_sample();
-
-                    let score = expected_reward;
+                    // includes the reward of the edge
+                    let score = tree.get_statistics_lock(edge.get_target_node()).expected_sample();
 
                     if score > max_ucb_score {
                         max_ucb_score = score;
-                        best_edge = edge;
+                        best_edge = *edge_id;
                     }
                 }
                 best_edge
             },
-            Player::Environment => {
-                node.edges().choose(&mut rand::thread_rng()).unwrap()
-            }
+            Player::Environment => sample_transition(tree, edges, rng)
         }
     }
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use crate::lib::Simulator;
-    use crate::lib::search_problem::HiddenState;
+    use crate::lib::search::expectimax::{expectimax, ExpectimaxConfig};
+    use crate::lib::search_problem::{HiddenState, Observation};
     use crate::lib::utils::RandomSimulator;
     use crate::lib::tzf8::{Action, Board, Player, TwoZeroFourEight, TwoZeroFourEightSimulator, TwoZeroFourEightTreePolicy};
     use crate::lib::search::mcts::{initialise, MctsConfig, once};
+    use crate::lib::search::parallel::Parallelism;
 
-    fn board1() -> Board {
+    pub(crate) fn board1() -> Board {
         let mut  b = Board::new();
         b.cells[0][0] = 2;
         b.cells[0][1] = 2;
@@ -339,44 +366,69 @@ mod test {
     }
 
     #[test]
-    fn t1() {
+    fn t3() {
         let b = board1();
-        println!("{}", b);
-        println!("{}", b.apply(&Action::Left));
-        println!("{}", b.apply(&Action::Right));
-        println!("{}", b.apply(&Action::Up));
-        println!("{}", b.apply(&Action::Down));
+        let config = MctsConfig {
+            horizon: 20,
+            ..MctsConfig::new(
+                TwoZeroFourEight{},
+                vec![Player::Environment, Player::Agent],
+                TwoZeroFourEightTreePolicy{},
+                RandomSimulator{},
+            )
+        };
+        let trees = initialise(&config.search_problem, &b);
+        let mut rng = config.rng();
+        for _ in 0..10 {
+            once(&config, b.clone(), &trees, &mut rng);
+        }
     }
 
     #[test]
-    fn t2() {
-        let b = board1();
-        let sim = TwoZeroFourEightSimulator{};
-        let p = TwoZeroFourEight{};
-
-        let result = sim.simulate(&p, b, 1000, 1.0);
-
-        println!("{:?}", result);
+    fn merges_are_rewarded_once() {
+        let moved = board1().apply(&Action::Up);
+        assert_eq!(moved.reward(), 12.0);
+        // the tile placed next merges nothing
+        let placed = moved.apply(&moved.legal_actions()[0]);
+        assert_eq!(placed.reward(), 0.0);
+
+        // so the return of a move and a placement is the merges of the move
+        let config = ExpectimaxConfig {
+            search_problem: TwoZeroFourEight{},
+            heuristic: |_: &TwoZeroFourEight, _: &Board| vec![],
+            depth: 2,
+            discount: 1.0,
+        };
+        let result = expectimax(&config, &board1());
+        assert_eq!(result.get(&Action::Up).unwrap().value, 12.0);
     }
 
     #[test]
-    fn t3() {
-        let b = board1();
-        let config = MctsConfig {
-            search_problem: TwoZeroFourEight{},
-            players: vec![Player::Environment, Player::Agent],
-            tree_policy: TwoZeroFourEightTreePolicy{},
-            simulator: RandomSimulator{},
-            discount: 1.0,
+    fn simulator_rewards_the_moves() {
+        // the reward of the start state is on the edge into it, only the merges of the moves count
+        // the columns are full and merge nothing, the rows merge the same either way
+        let mut b = Board::new();
+        for row in 0..4 {
+            let tile = if row % 2 == 0 { 2 } else { 4 };
+            b.cells[row][0] = tile;
+            b.cells[row][1] = tile;
+        }
+        b.new_tile_sum = 8;
+        let returns = TwoZeroFourEightSimulator.simulate(&TwoZeroFourEight{}, b, 1, 1.0, &mut rand::thread_rng());
+        assert_eq!(returns, vec![(Player::Environment, 0.0), (Player::Agent, 24.0)]);
+    }
+
+    // A search of the agent with rollouts of 20 moves
+    pub(crate) fn config(parallelism: Parallelism) -> MctsConfig<TwoZeroFourEightTreePolicy, TwoZeroFourEight, RandomSimulator> {
+        MctsConfig {
             horizon: 20,
-        };
-        let nodes = initialise(&config.search_problem, &b);
-        for _ in 0..10 {
-            let mut arg = vec![];
-            for node in nodes.iter() {
-                arg.push(node)
-            }
-            once(&config, b.clone(), arg);
+            parallelism,
+            ..MctsConfig::new(
+                TwoZeroFourEight{},
+                vec![Player::Environment, Player::Agent],
+                TwoZeroFourEightTreePolicy{},
+                RandomSimulator{},
+            )
         }
     }
-}
\ No newline at end of file
+}
@@ -1,44 +1,23 @@
-use rand::seq::SliceRandom;
-use crate::lib::search_problem::{HiddenState, Observation, SearchProblem};
-use crate::lib::Simulator;
+use rand::{Rng, RngCore};
+use crate::lib::rollout::{PolicySimulator, UniformRollout};
+use crate::lib::search_problem::{Observation, SearchProblem};
+use crate::lib::{Rollout, Simulator};
 
+// Uniformly random rollouts
 pub(crate) struct RandomSimulator;
 
 impl<P> Simulator<P> for RandomSimulator where P: SearchProblem, P::Player: Copy {
-    fn simulate(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32) -> Vec<(P::Player, f32)> {
-        let mut all_players = problem.get_all_players();
-        let mut scores = vec![];
-        for player in all_players {
-            scores.push((player, 0.0))
-        }
-
-        let mut discount_factor = 1.0;
-        let mut current_state = state;
-        for _ in 0..horizon {
-            if current_state.is_terminal() {
-                break;
-            }
-
-            for (player, score) in scores.iter_mut() {
-                *score += discount_factor*problem.get_observation(&current_state, *player).reward()
-            }
-
-            let obs = problem.get_observation(&current_state, current_state.current_actor());
-
-
-            let actions = obs.legal_actions();
-            let action = actions.choose(&mut rand::thread_rng()).unwrap();
-            current_state = current_state.apply(&action);
-
-            discount_factor *= discount;
-        }
+    fn simulate(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32, rng: &mut dyn RngCore) -> Vec<(P::Player, f32)> {
+        self.simulate_with_actions(problem, state, horizon, discount, rng).values
+    }
 
-        scores
+    fn simulate_with_actions(&self, problem: &P, state: P::HiddenState, horizon: u32, discount: f32, rng: &mut dyn RngCore) -> Rollout<P> {
+        PolicySimulator { policy: UniformRollout }.simulate_with_actions(problem, state, horizon, discount, rng)
     }
 }
 
 
-pub(crate) fn index_for_player<P: PartialEq>(rewards: &Vec<(P, f32)>, player: &P) -> f32 {
+pub(crate) fn index_for_player<P: PartialEq>(rewards: &[(P, f32)], player: &P) -> f32 {
     for (p, r) in rewards.iter() {
         if *player == *p {
             return *r;
@@ -55,3 +34,43 @@ pub(crate) fn reward_for_all_players<P: SearchProblem>(p: &P, hidden_state: &P::
     }
     result
 }
+
+// Samples from the symmetric Dirichlet distribution with the given concentration over count components
+pub(crate) fn sample_dirichlet<R: Rng + ?Sized>(rng: &mut R, alpha: f32, count: usize) -> Vec<f32> {
+    let gammas: Vec<f32> = (0..count).map(|_| sample_gamma(rng, alpha)).collect();
+    let total: f32 = gammas.iter().sum();
+    if total > 0.0 {
+        gammas.iter().map(|gamma| gamma / total).collect()
+    } else {
+        // every component underflowed, which happens for very small concentrations
+        vec![1.0 / count as f32; count]
+    }
+}
+
+// Marsaglia and Tsang's method, boosted for shapes below 1
+fn sample_gamma<R: Rng + ?Sized>(rng: &mut R, shape: f32) -> f32 {
+    if shape < 1.0 {
+        let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+        return sample_gamma(rng, shape + 1.0) * u.powf(1.0 / shape)
+    }
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = sample_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue
+        }
+        let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v
+        }
+    }
+}
+
+// Box-Muller transform
+fn sample_normal<R: Rng + ?Sized>(rng: &mut R) -> f32 {
+    let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let v: f32 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+}
@@ -1,9 +1,39 @@
+// rustc only reads this lint at the crate root, an allow on the module declaration has no effect
+#![allow(special_module_name)]
 extern crate core;
 
 mod lib;
 
+use rand::seq::SliceRandom;
+use crate::lib::search::budget::Budget;
+use crate::lib::search::mcts::MctsConfig;
+use crate::lib::search::searcher::Searcher;
+use crate::lib::search_problem::{HiddenState, Observation};
+use crate::lib::tzf8::{Board, Player, TwoZeroFourEight, TwoZeroFourEightTreePolicy};
+use crate::lib::utils::RandomSimulator;
 
-
+// Plays a game of 2048, searching every move, and prints the final board with the score
 fn main() {
-    println!("Hello, world!");
+    let config = MctsConfig {
+        horizon: 20,
+        ..MctsConfig::new(
+            TwoZeroFourEight{},
+            vec![Player::Environment, Player::Agent],
+            TwoZeroFourEightTreePolicy{},
+            RandomSimulator{},
+        )
+    };
+    let mut searcher = Searcher::new(config, Board::start());
+    let mut rng = rand::thread_rng();
+    let mut score = 0.0;
+    while !searcher.hidden_state().is_terminal() {
+        let action = match searcher.hidden_state().current_actor() {
+            Player::Agent => searcher.search(Budget::Iterations(100)).best_action.unwrap(),
+            Player::Environment => *searcher.hidden_state().legal_actions().choose(&mut rng).unwrap(),
+        };
+        searcher.advance(&action);
+        score += searcher.hidden_state().reward();
+    }
+    println!("{}", searcher.hidden_state());
+    println!("Score: {}", score);
 }